@@ -1,7 +1,9 @@
 use std::env;
 
 fn main() {
-    if cfg!(target_os = "macos") {
+    // Only the Python Mote backend links against libpython
+    let python = env::var_os("CARGO_FEATURE_PYTHON").is_some();
+    if python && cfg!(target_os = "macos") {
         // Check for virtual environment
         if let Ok(venv_path) = env::var("VIRTUAL_ENV") {
             println!("cargo:rustc-link-arg=-Wl,-rpath,{}/lib", venv_path);
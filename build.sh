@@ -13,9 +13,15 @@ MACOS_DIR="${CONTENTS_DIR}/MacOS"
 RESOURCES_DIR="${CONTENTS_DIR}/Resources"
 VENV_DIR="${RESOURCES_DIR}/venv"
 
+# Set WITH_PYTHON=1 to drive the Mote through the Python library instead of the native driver
+CARGO_FLAGS=""
+if [ -n "${WITH_PYTHON}" ]; then
+    CARGO_FLAGS="--features python"
+fi
+
 # Build the release binaries
 echo "Building release binaries..."
-cargo build --release 
+cargo build --release ${CARGO_FLAGS}
 
 # Create the bundle structure
 echo "Creating app bundle structure..."
@@ -24,11 +30,13 @@ mkdir -p "${MACOS_DIR}"
 mkdir -p "${RESOURCES_DIR}"
 
 # Set up Python virtual environment
-echo "Setting up Python virtual environment..."
-python3 -m venv "${VENV_DIR}"
-source "${VENV_DIR}/bin/activate"
-pip install -r requirements.txt
-deactivate
+if [ -n "${WITH_PYTHON}" ]; then
+    echo "Setting up Python virtual environment..."
+    python3 -m venv "${VENV_DIR}"
+    source "${VENV_DIR}/bin/activate"
+    pip install -r requirements.txt
+    deactivate
+fi
 
 # Create launcher script
 echo "Creating launcher script..."
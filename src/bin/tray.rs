@@ -1,53 +1,152 @@
 use anyhow::{Result, Context};
-use rgb_daemon::{Command, Profile, ColorSetting};
-use std::path::PathBuf;
+use clap::Parser;
+use rgb_daemon::{ColorSetting, DeviceStatus, Event, Color};
+use rgb_daemon::config::Config;
+use rgb_daemon::effects::{EffectKind, EffectParams};
+use rgb_daemon::client::{self, BlockingClient, RgbClient, DEFAULT_SOCKET_PATH};
 use tray_icon::{Icon, TrayIconBuilder};
-use tray_icon::menu::{Menu, MenuEvent, MenuItem, MenuId, accelerator::Accelerator, PredefinedMenuItem};
-use winit::event_loop::{ControlFlow, EventLoop};
-use image;
+use tray_icon::menu::{Menu, MenuEvent, MenuItem, Submenu, accelerator::Accelerator, PredefinedMenuItem};
+use winit::event::Event as WinitEvent;
+use winit::event_loop::{ControlFlow, EventLoopBuilder, EventLoopProxy};
+use std::path::PathBuf;
 
-const SOCKET_PATH: &str = "/tmp/rgb-daemon.sock";
+const SOCKET_PATH: &str = DEFAULT_SOCKET_PATH;
 const ICON_ON_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/light_on.png");
 const ICON_OFF_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/light_off.png");
 
+// Color temperature shortcuts, listed after the profiles
+const WHITE_PRESETS: [(&str, u32); 3] = [
+    ("Warm White", 2700),
+    ("Neutral White", 4000),
+    ("Cool White", 6500),
+];
+
+// Entries of the brightness submenu, in percent
+const BRIGHTNESS_LEVELS: [u32; 5] = [100, 75, 50, 25, 10];
+
+/// Tray menu for the RGB daemon
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Config file to use instead of $XDG_CONFIG_HOME/rgbd/config.toml;
+    /// pass the daemon's, so the menu lists the same profiles
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+}
+
+// Sent from the daemon watcher so updates wake the event loop
+enum TrayUpdate {
+    Icon(&'static str),
+    Devices(Vec<DeviceStatus>),
+}
+
 // Define our menu items
 struct MenuItemDef {
-    id: &'static str,
-    label: &'static str,
-    profile: Option<Profile>,
-}
-
-const MENU_ITEMS: &[MenuItemDef] = &[
-    MenuItemDef { id: "profile_off", label: "Off", profile: Some(Profile::Off) },
-    MenuItemDef { id: "profile_red", label: "Red", profile: Some(Profile::Red) },
-    MenuItemDef { id: "profile_white", label: "White", profile: Some(Profile::White) },
-    MenuItemDef { id: "separator1", label: "-", profile: None },
-    MenuItemDef { id: "reconnect", label: "Reconnect Device", profile: None },
-    MenuItemDef { id: "separator2", label: "-", profile: None },
-    MenuItemDef { id: "quit", label: "Quit", profile: None },
-];
+    id: String,
+    label: String,
+    setting: Option<ColorSetting>,
+    // Only does something on devices with pixels of their own
+    needs_pixels: bool,
+}
+
+impl MenuItemDef {
+    fn new(id: &str, label: &str) -> Self {
+        Self { id: id.to_string(), label: label.to_string(), setting: None, needs_pixels: false }
+    }
+
+    fn setting(id: String, label: String, setting: ColorSetting) -> Self {
+        Self { id, label, setting: Some(setting), needs_pixels: false }
+    }
+}
+
+// One entry per configured profile and white preset, followed by the fixed actions
+fn menu_items(config: &Config) -> Vec<MenuItemDef> {
+    let mut items: Vec<MenuItemDef> = config.profiles.iter()
+        .map(|profile| MenuItemDef {
+            // Zones need per-pixel control
+            needs_pixels: !profile.zones.is_empty(),
+            ..MenuItemDef::setting(
+                format!("profile_{}", profile.name),
+                profile.name.clone(),
+                ColorSetting::Profile(profile.name.clone()),
+            )
+        })
+        .collect();
+    items.push(MenuItemDef::new("separator0", "-"));
+    items.extend(WHITE_PRESETS.iter().map(|&(label, kelvin)| MenuItemDef::setting(
+        format!("temperature_{}", kelvin),
+        format!("{} ({}K)", label, kelvin),
+        ColorSetting::Temperature(kelvin),
+    )));
+    // Following the sun needs to know where it is
+    if config.location.is_some() {
+        items.push(MenuItemDef::setting("circadian".to_string(), "Follow the Sun".to_string(), ColorSetting::Circadian));
+    }
+    items.push(MenuItemDef::new("effects", "Effects"));
+    items.extend(EffectKind::ALL.iter().map(|&kind| MenuItemDef {
+        needs_pixels: kind.needs_pixels(),
+        ..MenuItemDef::setting(
+            format!("effect_{}", kind.name()),
+            effect_label(kind),
+            ColorSetting::Effect { name: kind.name().to_string(), params: EffectParams::default() },
+        )
+    }));
+    items.extend([
+        MenuItemDef::new("brightness", "Brightness"),
+        MenuItemDef::new("separator1", "-"),
+        MenuItemDef::new("reconnect", "Reconnect Device"),
+        MenuItemDef::new("separator2", "-"),
+        MenuItemDef::new("quit", "Quit"),
+    ]);
+    items
+}
 
 fn main() -> Result<()> {
-    let event_loop = EventLoop::new()?;
+    let args = Args::parse();
+    let event_loop = EventLoopBuilder::<TrayUpdate>::with_user_event().build()?;
     let icon = load_icon(ICON_OFF_PATH)?;
+    let config = match &args.config {
+        Some(path) => Config::load_from(path)?,
+        None => Config::load()?,
+    };
+    let items = menu_items(&config);
 
     // Create menu
     let menu = Menu::new();
     
-    // Add all menu items
-    for item_def in MENU_ITEMS {
-        if item_def.label == "-" {
+    // Add all menu items, keeping those that need pixels to enable once
+    // we know what the devices can do
+    let mut pixel_items = Vec::new();
+    let mut effects = None;
+    for item_def in &items {
+        if item_def.id.starts_with("separator") {
             let _ = menu.append(&PredefinedMenuItem::separator());
             continue;
         }
+        if item_def.id == "brightness" {
+            menu.append(&brightness_menu(&item_def.label)?)?;
+            continue;
+        }
+        if item_def.id == "effects" {
+            let submenu = Submenu::new(&item_def.label, true);
+            menu.append(&submenu)?;
+            effects = Some(submenu);
+            continue;
+        }
         
         let menu_item = MenuItem::with_id(
-            item_def.id,
-            MenuId(item_def.label.to_string()),
+            item_def.id.as_str(),
+            &item_def.label,
             true,
             None::<Accelerator>
         );
-        menu.append(&menu_item)?;
+        match &effects {
+            Some(submenu) if item_def.id.starts_with("effect_") => submenu.append(&menu_item)?,
+            _ => menu.append(&menu_item)?,
+        }
+        if item_def.needs_pixels {
+            pixel_items.push(menu_item);
+        }
     }
 
     let tray_icon = TrayIconBuilder::new()
@@ -59,26 +158,30 @@ fn main() -> Result<()> {
     // Handle menu events
     let menu_channel = MenuEvent::receiver();
     
-    // Create a channel for icon updates
-    let (icon_tx, icon_rx) = std::sync::mpsc::channel();
+    // Keep the icon in sync with the daemon, whoever changed the lights
+    let proxy = event_loop.create_proxy();
+    std::thread::spawn(move || watch_daemon(proxy, config));
     
     std::thread::spawn(move || {
+        // One connection for the lifetime of the menu, opened on first use
+        let mut client: Option<BlockingClient> = None;
         while let Ok(event) = menu_channel.recv() {            
+            if let Some(percent) = event.id.0.strip_prefix("brightness_").and_then(|level| level.parse::<u32>().ok()) {
+                println!("Setting brightness to {}%", percent);
+                send_brightness(&mut client, percent as f32 / 100.0);
+                continue;
+            }
+
             // Find the matching menu item definition
-            if let Some(item_def) = MENU_ITEMS.iter().find(|item| item.id == event.id.0) {
-                match (item_def.id, item_def.profile) {
-                    (_, Some(profile)) => {
-                        println!("Switching to {:?} profile", profile);
-                        let icon_path = match profile {
-                            Profile::Off => ICON_OFF_PATH,
-                            _ => ICON_ON_PATH,
-                        };
-                        let _ = icon_tx.send(icon_path);
-                        send_profile(profile);
+            if let Some(item_def) = items.iter().find(|item| item.id == event.id.0) {
+                match (item_def.id.as_str(), &item_def.setting) {
+                    (_, Some(setting)) => {
+                        println!("Switching to {}", item_def.label);
+                        send_setting(&mut client, setting.clone());
                     }
                     ("reconnect", None) => {
                         println!("Reconnecting device...");
-                        send_reconnect();
+                        send_reconnect(&mut client);
                     }
                     ("quit", None) => {
                         println!("Exiting application");
@@ -92,12 +195,22 @@ fn main() -> Result<()> {
         }
     });
 
-    event_loop.run(move |_event, elwt| {
-        // Check for icon updates
-        if let Ok(icon_path) = icon_rx.try_recv() {
-            if let Ok(new_icon) = load_icon(icon_path) {
-                let _ = tray_icon.set_icon(Some(new_icon));
+    event_loop.run(move |event, elwt| {
+        match event {
+            WinitEvent::UserEvent(TrayUpdate::Icon(icon_path)) => {
+                if let Ok(new_icon) = load_icon(icon_path) {
+                    let _ = tray_icon.set_icon(Some(new_icon));
+                }
             }
+            WinitEvent::UserEvent(TrayUpdate::Devices(devices)) => {
+                let _ = tray_icon.set_tooltip(Some(tooltip_for(&devices)));
+                // Greyed out until a connected device can show them
+                let addressable = devices.iter().any(|device| device.connected && device.capabilities.addressable);
+                for item in &pixel_items {
+                    item.set_enabled(addressable);
+                }
+            }
+            _ => {}
         }
         elwt.set_control_flow(ControlFlow::Wait);
     })?;
@@ -105,6 +218,26 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+fn brightness_menu(label: &str) -> Result<Submenu> {
+    let submenu = Submenu::new(label, true);
+    for percent in BRIGHTNESS_LEVELS {
+        let item = MenuItem::with_id(format!("brightness_{}", percent), format!("{}%", percent), true, None::<Accelerator>);
+        submenu.append(&item)?;
+    }
+    Ok(submenu)
+}
+
+// "theater-chase" becomes "Theater Chase"
+fn effect_label(kind: EffectKind) -> String {
+    kind.name().split('-')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars.next().map(|first| first.to_ascii_uppercase().to_string() + chars.as_str()).unwrap_or_default()
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 fn load_icon(icon_path: &str) -> Result<Icon> {
     let image = image::open(icon_path)
         .context("Failed to open icon file")?
@@ -116,41 +249,117 @@ fn load_icon(icon_path: &str) -> Result<Icon> {
     Ok(icon)
 }
 
-fn send_profile(profile: Profile) {
-    println!("Creating runtime to send profile: {:?}", profile);
-    tokio::runtime::Runtime::new()
-        .unwrap()
-        .block_on(async {
-            let socket_path = PathBuf::from(SOCKET_PATH);
-            let setting = ColorSetting::Profile(profile);
-            println!("Sending profile command to daemon at {}", socket_path.display());
-            if let Err(e) = send_command(socket_path, Command::SetProfile(setting)).await {
-                eprintln!("Failed to send profile command: {}", e);
-            } else {
-                println!("Successfully sent profile command");
-            }
-        });
-}
-
-fn send_reconnect() {
-    println!("Creating runtime to send reconnect command");
-    tokio::runtime::Runtime::new()
-        .unwrap()
-        .block_on(async {
-            let socket_path = PathBuf::from(SOCKET_PATH);
-            println!("Sending reconnect command to daemon at {}", socket_path.display());
-            if let Err(e) = send_command(socket_path, Command::Reconnect).await {
-                eprintln!("Failed to send reconnect command: {}", e);
-            } else {
-                println!("Successfully sent reconnect command");
+fn icon_for(config: &Config, setting: &ColorSetting) -> &'static str {
+    let off = match setting {
+        ColorSetting::Profile(name) => config.profile(name).is_some_and(|profile| profile.is_off()),
+        ColorSetting::Custom(color) => color.is_off(),
+        ColorSetting::Temperature(_) | ColorSetting::Effect { .. } | ColorSetting::Circadian => false,
+    };
+    if off { ICON_OFF_PATH } else { ICON_ON_PATH }
+}
+
+// One line per device, so the tooltip shows what each can do
+fn tooltip_for(devices: &[DeviceStatus]) -> String {
+    let mut tooltip = String::from("RGB Controller");
+    for device in devices {
+        let caps = &device.capabilities;
+        let detail = if !device.connected {
+            "disconnected".to_string()
+        } else if caps.addressable {
+            format!("{} pixels, {} zones", caps.pixels, caps.zones)
+        } else {
+            "single color".to_string()
+        };
+        tooltip.push_str(&format!("\n{}: {}", device.name, detail));
+    }
+    tooltip
+}
+
+fn watch_daemon(proxy: EventLoopProxy<TrayUpdate>, config: Config) {
+    loop {
+        if let Err(e) = follow_events(&proxy, &config) {
+            eprintln!("Lost event stream from daemon: {}", e);
+        }
+        std::thread::sleep(std::time::Duration::from_secs(2));
+    }
+}
+
+fn follow_events(proxy: &EventLoopProxy<TrayUpdate>, config: &Config) -> Result<()> {
+    let mut client = RgbClient::connect_blocking(SOCKET_PATH)?;
+
+    // Pick up the current state before following changes
+    let state = client.state()?;
+    let _ = proxy.send_event(TrayUpdate::Devices(state.devices));
+    let mut setting = state.setting;
+    let mut brightness = state.brightness;
+
+    // Devices coming and going change the tooltip, so it needs its own connection
+    let mut status_client = RgbClient::connect_blocking(SOCKET_PATH)?;
+    let mut events = client.subscribe()?;
+    let mut shown = None;
+    loop {
+        // Zero brightness is off too, whatever the setting
+        let icon_path = match &setting {
+            Some(_) if brightness <= 0.0 => ICON_OFF_PATH,
+            Some(setting) => icon_for(config, setting),
+            None => ICON_OFF_PATH,
+        };
+        if shown != Some(icon_path) {
+            let _ = proxy.send_event(TrayUpdate::Icon(icon_path));
+            shown = Some(icon_path);
+        }
+
+        let Some(event) = events.next() else {
+            return Ok(());
+        };
+        match event? {
+            Event::ConnectionChanged { .. } => {
+                let devices = status_client.state()?.devices;
+                let _ = proxy.send_event(TrayUpdate::Devices(devices));
             }
-        });
+            Event::ProfileChanged(changed) => setting = Some(changed),
+            Event::ColorChanged(rgb) => setting = Some(ColorSetting::Custom(Color {
+                red: rgb.red,
+                green: rgb.green,
+                blue: rgb.blue,
+            })),
+            Event::BrightnessChanged(changed) => brightness = changed,
+            _ => {}
+        }
+    }
 }
 
-async fn send_command(socket_path: PathBuf, command: Command) -> Result<()> {
-    use tokio::io::AsyncWriteExt;
-    let mut stream = tokio::net::UnixStream::connect(socket_path).await?;
-    let command_json = serde_json::to_vec(&command)?;
-    stream.write_all(&command_json).await?;
-    Ok(())
-} 
\ No newline at end of file
+fn with_client<T>(
+    client: &mut Option<BlockingClient>,
+    f: impl FnOnce(&mut BlockingClient) -> client::Result<T>,
+) -> client::Result<T> {
+    let client = match client {
+        Some(client) => client,
+        None => client.insert(RgbClient::connect_blocking(SOCKET_PATH)?),
+    };
+    f(client)
+}
+
+fn send_setting(client: &mut Option<BlockingClient>, setting: ColorSetting) {
+    println!("Sending profile command to daemon at {}", SOCKET_PATH);
+    match with_client(client, |client| client.set_profile(setting)) {
+        Ok(()) => println!("Profile applied"),
+        Err(e) => eprintln!("Failed to apply profile: {}", e),
+    }
+}
+
+fn send_brightness(client: &mut Option<BlockingClient>, brightness: f32) {
+    println!("Sending brightness command to daemon at {}", SOCKET_PATH);
+    match with_client(client, |client| client.set_brightness(brightness)) {
+        Ok(()) => println!("Brightness applied"),
+        Err(e) => eprintln!("Failed to set brightness: {}", e),
+    }
+}
+
+fn send_reconnect(client: &mut Option<BlockingClient>) {
+    println!("Sending reconnect command to daemon at {}", SOCKET_PATH);
+    match with_client(client, |client| client.reconnect()) {
+        Ok(()) => println!("Device reconnected"),
+        Err(e) => eprintln!("Failed to reconnect device: {}", e),
+    }
+}
@@ -0,0 +1,198 @@
+use crate::rgb_controller::profiles::{MAX_KELVIN, MIN_KELVIN};
+use crate::solar::{self, Location};
+use crate::Color;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// The `[circadian]` table: what `ColorSetting::Circadian` shows for each
+/// height of the sun. An empty table gets the default curve.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Circadian {
+    #[serde(rename = "point")]
+    pub points: Vec<CurvePoint>,
+}
+
+/// One point of the curve. Between points, temperatures and brightness
+/// glide evenly; below the lowest and above the highest they hold.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CurvePoint {
+    /// Degrees the sun is above the horizon; negative once it has set.
+    pub elevation: f32,
+    /// White at this color temperature...
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kelvin: Option<u32>,
+    /// ...or a color, for the deep reds of the night.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<Color>,
+    #[serde(default = "full_brightness")]
+    pub brightness: f32,
+}
+
+fn full_brightness() -> f32 {
+    1.0
+}
+
+impl CurvePoint {
+    fn kelvin(elevation: f32, kelvin: u32, brightness: f32) -> Self {
+        Self { elevation, kelvin: Some(kelvin), color: None, brightness }
+    }
+
+    fn color(&self) -> Color {
+        match (self.kelvin, self.color) {
+            (Some(kelvin), _) => Color::from_kelvin(kelvin as f32),
+            (None, Some(color)) => color,
+            (None, None) => Color::WHITE,
+        }
+    }
+}
+
+/// Where on the curve the lights are, for `rgbd status`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CurvePosition {
+    /// How high the sun is, in degrees.
+    pub elevation: f32,
+    /// The color temperature, when both neighbouring points give one.
+    pub kelvin: Option<u32>,
+    /// The color before brightness and white point.
+    pub color: Color,
+    pub brightness: f32,
+}
+
+impl CurvePosition {
+    /// The color to show, before each device's white point.
+    pub fn output(&self) -> Color {
+        self.color.scale(self.brightness)
+    }
+}
+
+impl Default for Circadian {
+    // Daylight while the sun is up, warm through sunset, deep red at night
+    fn default() -> Self {
+        Self {
+            points: vec![
+                CurvePoint { elevation: -12.0, kelvin: None, color: Some(Color { red: 255, green: 24, blue: 0 }), brightness: 0.35 },
+                CurvePoint::kelvin(-6.0, 1900, 0.6),
+                CurvePoint::kelvin(0.0, 2700, 0.85),
+                CurvePoint::kelvin(10.0, 4000, 1.0),
+                CurvePoint::kelvin(30.0, 6500, 1.0),
+            ],
+        }
+    }
+}
+
+impl Circadian {
+    pub fn validate(&self) -> Result<()> {
+        if self.points.is_empty() {
+            bail!("needs at least one point");
+        }
+        for point in &self.points {
+            if point.kelvin.is_some() == point.color.is_some() {
+                bail!("the point at {}° needs either a kelvin or a color", point.elevation);
+            }
+            if let Some(kelvin) = point.kelvin {
+                if !(MIN_KELVIN..=MAX_KELVIN).contains(&kelvin) {
+                    bail!("color temperatures go from {}K to {}K, not {}K", MIN_KELVIN, MAX_KELVIN, kelvin);
+                }
+            }
+            if !(0.0..=1.0).contains(&point.brightness) {
+                bail!("brightness goes from 0 to 1, not {}", point.brightness);
+            }
+        }
+        Ok(())
+    }
+
+    /// Where on the curve the sun puts us at `time`.
+    pub fn position(&self, location: &Location, time: DateTime<Utc>) -> CurvePosition {
+        self.at(solar::elevation(location, time) as f32)
+    }
+
+    fn at(&self, elevation: f32) -> CurvePosition {
+        let mut points: Vec<&CurvePoint> = self.points.iter().collect();
+        points.sort_by(|a, b| a.elevation.total_cmp(&b.elevation));
+        let below = points.iter().rev().find(|point| point.elevation <= elevation).or(points.first());
+        let above = points.iter().find(|point| point.elevation > elevation).or(points.last());
+        let (Some(below), Some(above)) = (below, above) else {
+            return CurvePosition { elevation, kelvin: None, color: Color::WHITE, brightness: 1.0 };
+        };
+
+        let span = above.elevation - below.elevation;
+        let t = if span > 0.0 { ((elevation - below.elevation) / span).clamp(0.0, 1.0) } else { 0.0 };
+        // Temperatures glide in Kelvin, so whites stay on the black-body curve
+        let (kelvin, color) = match (below.kelvin, above.kelvin) {
+            (Some(from), Some(to)) => {
+                let kelvin = from as f32 + (to as f32 - from as f32) * t;
+                (Some(kelvin.round() as u32), Color::from_kelvin(kelvin))
+            }
+            _ => (None, below.color().mix(above.color(), t)),
+        };
+        CurvePosition {
+            elevation,
+            kelvin,
+            color,
+            brightness: below.brightness + (above.brightness - below.brightness) * t,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn interpolates_between_points() {
+        let curve = Circadian::default();
+        let at = curve.at(5.0);
+        assert_eq!(at.kelvin, Some(3350));
+        assert_eq!(at.color, Color::from_kelvin(3350.0));
+        assert!(close(at.brightness, 0.925), "{}", at.brightness);
+
+        let at = curve.at(0.0);
+        assert_eq!(at.kelvin, Some(2700));
+        assert!(close(at.brightness, 0.85), "{}", at.brightness);
+
+        // A color on one side mixes colors instead of temperatures
+        let at = curve.at(-9.0);
+        assert_eq!(at.kelvin, None);
+        assert_eq!(at.color, Color { red: 255, green: 24, blue: 0 }.mix(Color::from_kelvin(1900.0), 0.5));
+        assert!(close(at.brightness, 0.475), "{}", at.brightness);
+    }
+
+    #[test]
+    fn holds_past_the_ends() {
+        let curve = Circadian::default();
+        let night = curve.at(-90.0);
+        assert_eq!(night.kelvin, None);
+        assert_eq!(night.color, Color { red: 255, green: 24, blue: 0 });
+        assert!(close(night.brightness, 0.35));
+
+        let noon = curve.at(90.0);
+        assert_eq!(noon.kelvin, Some(6500));
+        assert!(close(noon.brightness, 1.0));
+        assert_eq!(noon.elevation, 90.0);
+
+        // Points in any order
+        let reversed = Circadian { points: curve.points.iter().rev().cloned().collect() };
+        assert_eq!(reversed.at(90.0).kelvin, Some(6500));
+        assert_eq!(reversed.at(5.0).kelvin, Some(3350));
+    }
+
+    #[test]
+    fn empty_tables_get_the_default_curve() {
+        let curve: Circadian = toml::from_str("").unwrap();
+        assert_eq!(curve.points.len(), Circadian::default().points.len());
+        assert!(curve.validate().is_ok());
+
+        // A curve emptied some other way shows full white
+        let empty = Circadian { points: Vec::new() };
+        assert!(empty.validate().is_err());
+        let at = empty.at(10.0);
+        assert_eq!((at.kelvin, at.color), (None, Color::WHITE));
+        assert!(close(at.brightness, 1.0));
+    }
+}
@@ -0,0 +1,347 @@
+use crate::layers::Layer;
+use crate::protocol;
+use crate::schedule::ScheduleStatus;
+use crate::{Color, ColorSetting, Command, DaemonStatus, DeviceFailure, ErrorCode, Event, Response, RgbCommand};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::BufReader;
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::UnixStream;
+
+pub const DEFAULT_SOCKET_PATH: &str = "/tmp/rgb-daemon.sock";
+
+// The daemon answers once the first frame is written, but reconnecting has to
+// reopen the device, which can take a moment.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// The daemon socket couldn't be reached, usually because rgbd isn't running.
+    Connect(io::Error),
+    Io(io::Error),
+    Timeout,
+    /// The daemon closed the connection before answering.
+    Closed,
+    /// The daemon answered with something we didn't ask for or couldn't parse.
+    Protocol(String),
+    /// The daemon processed the request and reported a failure. For commands
+    /// sent to several devices, `failures` lists the ones that failed.
+    Daemon { code: ErrorCode, message: String, failures: Vec<DeviceFailure> },
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Connect(e) => write!(f, "failed to connect to daemon: {}", e),
+            ClientError::Io(e) => write!(f, "daemon connection failed: {}", e),
+            ClientError::Timeout => write!(f, "timed out waiting for the daemon"),
+            ClientError::Closed => write!(f, "daemon closed the connection without responding"),
+            ClientError::Protocol(message) => write!(f, "protocol error: {}", message),
+            ClientError::Daemon { code, message, .. } => write!(f, "daemon error ({:?}): {}", code, message),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Connect(e) | ClientError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+struct Connection {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Connection {
+    async fn open(socket_path: &Path) -> Result<Self> {
+        let stream = UnixStream::connect(socket_path).await.map_err(ClientError::Connect)?;
+        let (reader, writer) = stream.into_split();
+        Ok(Self { reader: BufReader::new(reader), writer })
+    }
+
+    async fn send(&mut self, command: &Command) -> Result<()> {
+        protocol::write_frame(&mut self.writer, command).await.map_err(into_client_error)
+    }
+
+    async fn receive(&mut self) -> Result<Response> {
+        match protocol::read_message::<_, Response>(&mut self.reader).await {
+            Ok(Some(response)) => Ok(response),
+            Ok(None) => Err(ClientError::Closed),
+            Err(e) => Err(into_client_error(e)),
+        }
+    }
+
+    async fn round_trip(&mut self, command: &Command) -> Result<Response> {
+        self.send(command).await?;
+        self.receive().await
+    }
+}
+
+fn into_client_error(e: anyhow::Error) -> ClientError {
+    match e.downcast::<io::Error>() {
+        Ok(e) => ClientError::Io(e),
+        Err(e) => ClientError::Protocol(e.to_string()),
+    }
+}
+
+fn expect_ok(response: Response) -> Result<()> {
+    match response {
+        Response::Ok => Ok(()),
+        other => Err(ClientError::Protocol(format!("expected Ok, got {:?}", other))),
+    }
+}
+
+/// Async client for the daemon socket. The connection is opened once and
+/// reused for every request; if the daemon restarted in between, the request
+/// is retried once on a fresh connection.
+pub struct RgbClient {
+    socket_path: PathBuf,
+    timeout: Duration,
+    connection: Option<Connection>,
+}
+
+impl RgbClient {
+    pub async fn connect(socket_path: impl Into<PathBuf>) -> Result<Self> {
+        let socket_path = socket_path.into();
+        let connection = Connection::open(&socket_path).await?;
+        Ok(Self {
+            socket_path,
+            timeout: DEFAULT_TIMEOUT,
+            connection: Some(connection),
+        })
+    }
+
+    /// Connects on a private single-threaded runtime, for callers without one.
+    pub fn connect_blocking(socket_path: impl Into<PathBuf>) -> Result<BlockingClient> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(ClientError::Io)?;
+        let inner = runtime.block_on(Self::connect(socket_path))?;
+        Ok(BlockingClient { runtime, inner })
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    /// Sends a command and returns the daemon's response. `Response::Error`
+    /// is turned into `ClientError::Daemon`.
+    pub async fn request(&mut self, command: Command) -> Result<Response> {
+        let response = match tokio::time::timeout(self.timeout, self.exchange(&command)).await {
+            Ok(response) => response,
+            Err(_) => {
+                // A late reply would be read as the answer to the next request
+                self.connection = None;
+                return Err(ClientError::Timeout);
+            }
+        };
+        match response? {
+            Response::Error { code, message, failures } => Err(ClientError::Daemon { code, message, failures }),
+            response => Ok(response),
+        }
+    }
+
+    async fn exchange(&mut self, command: &Command) -> Result<Response> {
+        if let Some(connection) = self.connection.as_mut() {
+            match connection.round_trip(command).await {
+                Ok(response) => return Ok(response),
+                Err(ClientError::Io(_)) | Err(ClientError::Closed) => {
+                    // Stale connection, e.g. the daemon restarted since we last talked
+                    self.connection = None;
+                }
+                Err(e) => {
+                    self.connection = None;
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut connection = Connection::open(&self.socket_path).await?;
+        let result = connection.round_trip(command).await;
+        if result.is_ok() {
+            self.connection = Some(connection);
+        }
+        result
+    }
+
+    pub async fn set_color(&mut self, red: u8, green: u8, blue: u8) -> Result<()> {
+        let response = self.request(Command::SetColor(RgbCommand { red, green, blue })).await?;
+        expect_ok(response)
+    }
+
+    pub async fn set_profile(&mut self, setting: impl Into<ColorSetting>) -> Result<()> {
+        let response = self.request(Command::SetProfile(setting.into())).await?;
+        expect_ok(response)
+    }
+
+    pub async fn set_zone(&mut self, zone: impl Into<String>, color: Color) -> Result<()> {
+        let response = self.request(Command::SetZone { zone: zone.into(), color }).await?;
+        expect_ok(response)
+    }
+
+    pub async fn set_brightness(&mut self, brightness: f32) -> Result<()> {
+        let response = self.request(Command::SetBrightness(brightness)).await?;
+        expect_ok(response)
+    }
+
+    pub async fn push_layer(&mut self, layer: Layer) -> Result<()> {
+        let response = self.request(Command::PushLayer(layer)).await?;
+        expect_ok(response)
+    }
+
+    pub async fn pop_layer(&mut self, id: impl Into<String>) -> Result<()> {
+        let response = self.request(Command::PopLayer { id: id.into() }).await?;
+        expect_ok(response)
+    }
+
+    pub async fn schedule(&mut self) -> Result<ScheduleStatus> {
+        match self.request(Command::GetSchedule).await? {
+            Response::Schedule(status) => Ok(status),
+            other => Err(ClientError::Protocol(format!("expected Schedule, got {:?}", other))),
+        }
+    }
+
+    /// Pauses the schedule for `duration`, or until `resume_schedule` without one.
+    pub async fn pause_schedule(&mut self, duration: Option<Duration>) -> Result<()> {
+        let duration_ms = duration.map(|duration| u64::try_from(duration.as_millis()).unwrap_or(u64::MAX));
+        let response = self.request(Command::PauseSchedule { duration_ms }).await?;
+        expect_ok(response)
+    }
+
+    pub async fn resume_schedule(&mut self) -> Result<()> {
+        let response = self.request(Command::ResumeSchedule).await?;
+        expect_ok(response)
+    }
+
+    /// Asks the daemon to recreate its controller and restore the last state.
+    pub async fn reconnect(&mut self) -> Result<()> {
+        let response = self.request(Command::Reconnect).await?;
+        expect_ok(response)
+    }
+
+    pub async fn state(&mut self) -> Result<DaemonStatus> {
+        match self.request(Command::GetState).await? {
+            Response::State(status) => Ok(*status),
+            other => Err(ClientError::Protocol(format!("expected State, got {:?}", other))),
+        }
+    }
+
+    /// Turns this connection into an event stream. The stream has no timeout;
+    /// it waits for as long as it takes for the next change.
+    pub async fn subscribe(mut self) -> Result<EventStream> {
+        let response = self.request(Command::Subscribe).await?;
+        expect_ok(response)?;
+        let connection = self.connection.take().ok_or(ClientError::Closed)?;
+        Ok(EventStream { connection })
+    }
+}
+
+pub struct EventStream {
+    connection: Connection,
+}
+
+impl EventStream {
+    /// Waits for the next event. Returns `None` when the daemon shuts down.
+    pub async fn next_event(&mut self) -> Result<Option<Event>> {
+        match self.connection.receive().await {
+            Ok(Response::Event(event)) => Ok(Some(event)),
+            Ok(Response::Error { code, message, failures }) => Err(ClientError::Daemon { code, message, failures }),
+            Ok(other) => Err(ClientError::Protocol(format!("expected Event, got {:?}", other))),
+            Err(ClientError::Closed) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Blocking wrapper around `RgbClient` that owns its own runtime.
+pub struct BlockingClient {
+    runtime: tokio::runtime::Runtime,
+    inner: RgbClient,
+}
+
+impl BlockingClient {
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.inner = self.inner.with_timeout(timeout);
+        self
+    }
+
+    pub fn request(&mut self, command: Command) -> Result<Response> {
+        self.runtime.block_on(self.inner.request(command))
+    }
+
+    pub fn set_color(&mut self, red: u8, green: u8, blue: u8) -> Result<()> {
+        self.runtime.block_on(self.inner.set_color(red, green, blue))
+    }
+
+    pub fn set_profile(&mut self, setting: impl Into<ColorSetting>) -> Result<()> {
+        self.runtime.block_on(self.inner.set_profile(setting))
+    }
+
+    pub fn set_zone(&mut self, zone: impl Into<String>, color: Color) -> Result<()> {
+        self.runtime.block_on(self.inner.set_zone(zone, color))
+    }
+
+    pub fn set_brightness(&mut self, brightness: f32) -> Result<()> {
+        self.runtime.block_on(self.inner.set_brightness(brightness))
+    }
+
+    pub fn push_layer(&mut self, layer: Layer) -> Result<()> {
+        self.runtime.block_on(self.inner.push_layer(layer))
+    }
+
+    pub fn pop_layer(&mut self, id: impl Into<String>) -> Result<()> {
+        self.runtime.block_on(self.inner.pop_layer(id))
+    }
+
+    pub fn schedule(&mut self) -> Result<ScheduleStatus> {
+        self.runtime.block_on(self.inner.schedule())
+    }
+
+    pub fn pause_schedule(&mut self, duration: Option<Duration>) -> Result<()> {
+        self.runtime.block_on(self.inner.pause_schedule(duration))
+    }
+
+    pub fn resume_schedule(&mut self) -> Result<()> {
+        self.runtime.block_on(self.inner.resume_schedule())
+    }
+
+    pub fn reconnect(&mut self) -> Result<()> {
+        self.runtime.block_on(self.inner.reconnect())
+    }
+
+    pub fn state(&mut self) -> Result<DaemonStatus> {
+        self.runtime.block_on(self.inner.state())
+    }
+
+    pub fn subscribe(self) -> Result<BlockingEventStream> {
+        let inner = self.runtime.block_on(self.inner.subscribe())?;
+        Ok(BlockingEventStream { runtime: self.runtime, inner })
+    }
+}
+
+pub struct BlockingEventStream {
+    runtime: tokio::runtime::Runtime,
+    inner: EventStream,
+}
+
+impl Iterator for BlockingEventStream {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.runtime.block_on(self.inner.next_event()).transpose()
+    }
+}
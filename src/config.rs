@@ -0,0 +1,119 @@
+use crate::circadian::Circadian;
+use crate::layout::Layout;
+use crate::rgb_controller::factory::ControllerConfig;
+use crate::rgb_controller::profiles::{Profile, TransitionSpec};
+use crate::schedule::Schedule;
+use crate::solar::Location;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::path::{Path, PathBuf};
+
+/// `$XDG_CONFIG_HOME/rgbd`, falling back to `~/.config/rgbd`.
+pub fn config_dir() -> PathBuf {
+    xdg_dir("XDG_CONFIG_HOME", ".config").join("rgbd")
+}
+
+pub(crate) fn xdg_dir(var: &str, fallback: &str) -> PathBuf {
+    match std::env::var_os(var) {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME").unwrap_or_default()).join(fallback),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    #[serde(default = "Profile::builtin", rename = "profile")]
+    pub profiles: Vec<Profile>,
+    /// Devices to drive, from either a single `[controller]` table or
+    /// several `[[controller]]` entries.
+    #[serde(default = "default_controllers", rename = "controller", deserialize_with = "one_or_many")]
+    pub controllers: Vec<ControllerConfig>,
+    /// Named zones, from `[[zone]]` tables.
+    #[serde(default, rename = "zone")]
+    pub layout: Layout,
+    /// Defaults for every transition, under whatever profiles and requests pick.
+    #[serde(default)]
+    pub transition: TransitionSpec,
+    /// Rules that switch the lights by the clock.
+    #[serde(default)]
+    pub schedule: Schedule,
+    /// Latitude and longitude, for following the sun.
+    #[serde(default)]
+    pub location: Option<Location>,
+    /// The curve `ColorSetting::Circadian` follows.
+    #[serde(default)]
+    pub circadian: Circadian,
+}
+
+fn default_controllers() -> Vec<ControllerConfig> {
+    vec![ControllerConfig::default()]
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<ControllerConfig>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        Many(Vec<ControllerConfig>),
+        One(ControllerConfig),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::Many(controllers) => controllers,
+        OneOrMany::One(controller) => vec![controller],
+    })
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            profiles: Profile::builtin(),
+            controllers: default_controllers(),
+            layout: Layout::default(),
+            transition: TransitionSpec::default(),
+            schedule: Schedule::default(),
+            location: None,
+            circadian: Circadian::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads `config.toml` (or `config.json`) from the config directory, or
+    /// the built-in defaults if neither exists.
+    pub fn load() -> Result<Self> {
+        let dir = config_dir();
+        for name in ["config.toml", "config.json"] {
+            let path = dir.join(name);
+            if path.exists() {
+                return Self::load_from(&path);
+            }
+        }
+        Ok(Self::default())
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let config: Config = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&contents).map_err(anyhow::Error::from)
+        } else {
+            toml::from_str(&contents).map_err(anyhow::Error::from)
+        }
+        .with_context(|| format!("Invalid config file {}", path.display()))?;
+        let devices: Vec<String> = config.controllers.iter().map(ControllerConfig::device_name).collect();
+        config.layout.validate(&devices)
+            .with_context(|| format!("Invalid zone layout in {}", path.display()))?;
+        if let Some(location) = &config.location {
+            location.validate().with_context(|| format!("Invalid location in {}", path.display()))?;
+        }
+        config.circadian.validate()
+            .with_context(|| format!("Invalid circadian curve in {}", path.display()))?;
+        config.schedule.validate(config.location.as_ref())
+            .with_context(|| format!("Invalid schedule in {}", path.display()))?;
+        Ok(config)
+    }
+
+    /// Looks up a profile by name, ignoring case.
+    pub fn profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| profile.name.eq_ignore_ascii_case(name))
+    }
+}
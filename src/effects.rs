@@ -0,0 +1,374 @@
+use crate::rgb_controller::profiles::Color;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::TAU;
+
+/// An animation, drawn a frame at a time by the render thread.
+pub trait Effect: Send {
+    /// Draws the frame `elapsed` seconds in, `dt` seconds after the last one.
+    /// `frame` holds every pixel, channel after channel.
+    fn render(&mut self, elapsed: f32, dt: f32, frame: &mut [Color]);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum EffectKind {
+    /// The color swelling and fading
+    Breathing,
+    /// A rainbow running along each strip
+    Rainbow,
+    /// Every pixel turning through the hues together
+    ColorWheel,
+    /// Every third pixel lit, marching along
+    TheaterChase,
+    /// A bright head with a fading tail
+    Comet,
+    /// Warm, uneven flicker
+    Candle,
+    /// Flames rising from the start of each strip
+    Fire,
+    /// Pixels lighting up and fading at random
+    Twinkle,
+    /// A dot sweeping back and forth
+    Larson,
+}
+
+impl EffectKind {
+    pub const ALL: [EffectKind; 9] = [
+        EffectKind::Breathing,
+        EffectKind::Rainbow,
+        EffectKind::ColorWheel,
+        EffectKind::TheaterChase,
+        EffectKind::Comet,
+        EffectKind::Candle,
+        EffectKind::Fire,
+        EffectKind::Twinkle,
+        EffectKind::Larson,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EffectKind::Breathing => "breathing",
+            EffectKind::Rainbow => "rainbow",
+            EffectKind::ColorWheel => "color-wheel",
+            EffectKind::TheaterChase => "theater-chase",
+            EffectKind::Comet => "comet",
+            EffectKind::Candle => "candle",
+            EffectKind::Fire => "fire",
+            EffectKind::Twinkle => "twinkle",
+            EffectKind::Larson => "larson",
+        }
+    }
+
+    /// Whether the effect moves along a strip, and so shows nothing useful
+    /// on a single-color device.
+    pub fn needs_pixels(self) -> bool {
+        matches!(self, EffectKind::Rainbow | EffectKind::TheaterChase | EffectKind::Comet | EffectKind::Fire | EffectKind::Larson)
+    }
+
+    /// Looks an effect up by name, ignoring case.
+    pub fn from_name(name: &str) -> Option<EffectKind> {
+        EffectKind::ALL.into_iter().find(|kind| kind.name().eq_ignore_ascii_case(name))
+    }
+
+    // The color used when the request doesn't pick one
+    fn default_color(self) -> Color {
+        match self {
+            EffectKind::Candle => Color::from_kelvin(1900.0),
+            EffectKind::Larson | EffectKind::Comet => Color::RED,
+            _ => Color::WHITE,
+        }
+    }
+}
+
+/// Knobs shared by every effect. Effects ignore the ones they have no use for.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EffectParams {
+    /// 1 is the effect's natural pace, 2 twice as fast.
+    pub speed: f32,
+    /// The effect's own default when unset.
+    pub color: Option<Color>,
+    /// Shown where the effect leaves pixels dark.
+    pub background: Color,
+}
+
+impl Default for EffectParams {
+    fn default() -> Self {
+        Self { speed: 1.0, color: None, background: Color::OFF }
+    }
+}
+
+/// Sets `kind` up for a device with `channels[i]` pixels on channel `i`.
+pub fn build(kind: EffectKind, params: &EffectParams, channels: &[usize]) -> Box<dyn Effect> {
+    let speed = params.speed.max(0.0);
+    let color = params.color.unwrap_or(kind.default_color());
+    let background = params.background;
+    let channels = channels.to_vec();
+    let rng = StdRng::from_entropy();
+    match kind {
+        EffectKind::Breathing => Box::new(Breathing { speed, color, background }),
+        EffectKind::Rainbow => Box::new(Rainbow { speed, channels }),
+        EffectKind::ColorWheel => Box::new(ColorWheel { speed }),
+        EffectKind::TheaterChase => Box::new(TheaterChase { speed, color, background, channels }),
+        EffectKind::Comet => Box::new(Comet { speed, color, background, channels }),
+        EffectKind::Candle => {
+            let flames = vec![1.0; channels.len()];
+            Box::new(Candle { speed, color, channels, flames, rng })
+        }
+        EffectKind::Fire => {
+            let heat = channels.iter().map(|&pixels| vec![0.0; pixels]).collect();
+            Box::new(Fire { speed, channels, heat, rng })
+        }
+        EffectKind::Twinkle => {
+            let levels = vec![0.0; channels.iter().sum()];
+            Box::new(Twinkle { speed, color, background, levels, rng })
+        }
+        EffectKind::Larson => Box::new(Larson { speed, color, background, channels }),
+    }
+}
+
+// Splits a frame into one slice per channel
+fn strips<'a>(frame: &'a mut [Color], channels: &'a [usize]) -> impl Iterator<Item = &'a mut [Color]> {
+    let mut rest = frame;
+    channels.iter().map(move |&pixels| {
+        let pixels = pixels.min(rest.len());
+        let (strip, tail) = std::mem::take(&mut rest).split_at_mut(pixels);
+        rest = tail;
+        strip
+    })
+}
+
+struct Breathing {
+    speed: f32,
+    color: Color,
+    background: Color,
+}
+
+impl Effect for Breathing {
+    fn render(&mut self, elapsed: f32, _dt: f32, frame: &mut [Color]) {
+        // One breath every four seconds
+        let level = 0.5 - 0.5 * (elapsed * self.speed * TAU / 4.0).cos();
+        frame.fill(self.background.mix(self.color, level));
+    }
+}
+
+struct Rainbow {
+    speed: f32,
+    channels: Vec<usize>,
+}
+
+impl Effect for Rainbow {
+    fn render(&mut self, elapsed: f32, _dt: f32, frame: &mut [Color]) {
+        let shift = elapsed * self.speed * 90.0;
+        for strip in strips(frame, &self.channels) {
+            let len = strip.len() as f32;
+            for (i, pixel) in strip.iter_mut().enumerate() {
+                *pixel = Color::from_hsv(i as f32 / len * 360.0 + shift, 1.0, 1.0);
+            }
+        }
+    }
+}
+
+struct ColorWheel {
+    speed: f32,
+}
+
+impl Effect for ColorWheel {
+    fn render(&mut self, elapsed: f32, _dt: f32, frame: &mut [Color]) {
+        frame.fill(Color::from_hsv(elapsed * self.speed * 36.0, 1.0, 1.0));
+    }
+}
+
+struct TheaterChase {
+    speed: f32,
+    color: Color,
+    background: Color,
+    channels: Vec<usize>,
+}
+
+impl Effect for TheaterChase {
+    fn render(&mut self, elapsed: f32, _dt: f32, frame: &mut [Color]) {
+        let offset = (elapsed * self.speed * 8.0) as usize % 3;
+        for strip in strips(frame, &self.channels) {
+            for (i, pixel) in strip.iter_mut().enumerate() {
+                *pixel = if i % 3 == offset { self.color } else { self.background };
+            }
+        }
+    }
+}
+
+struct Comet {
+    speed: f32,
+    color: Color,
+    background: Color,
+    channels: Vec<usize>,
+}
+
+impl Effect for Comet {
+    fn render(&mut self, elapsed: f32, _dt: f32, frame: &mut [Color]) {
+        for strip in strips(frame, &self.channels) {
+            let len = strip.len() as f32;
+            // Crosses the strip in two seconds, trailing a quarter of it
+            let head = (elapsed * self.speed * len / 2.0) % len;
+            let tail = (len / 4.0).max(1.0);
+            for (i, pixel) in strip.iter_mut().enumerate() {
+                let behind = (head - i as f32).rem_euclid(len);
+                let level = if behind < tail { 1.0 - behind / tail } else { 0.0 };
+                *pixel = self.background.mix(self.color, level);
+            }
+        }
+    }
+}
+
+struct Candle {
+    speed: f32,
+    color: Color,
+    channels: Vec<usize>,
+    // Current brightness of each strip's flame
+    flames: Vec<f32>,
+    rng: StdRng,
+}
+
+impl Effect for Candle {
+    fn render(&mut self, _elapsed: f32, dt: f32, frame: &mut [Color]) {
+        let pull = (dt * self.speed * 12.0).min(1.0);
+        for (strip, flame) in strips(frame, &self.channels).zip(&mut self.flames) {
+            let target = self.rng.gen_range(0.55..1.0);
+            *flame += (target - *flame) * pull;
+            strip.fill(self.color.scale(*flame));
+        }
+    }
+}
+
+struct Fire {
+    speed: f32,
+    channels: Vec<usize>,
+    // Heat of every pixel from 0 to 1, per strip
+    heat: Vec<Vec<f32>>,
+    rng: StdRng,
+}
+
+impl Fire {
+    // Black through red and yellow to white
+    fn color(heat: f32) -> Color {
+        let heat = heat.clamp(0.0, 1.0) * 3.0;
+        let ramp = |from: f32| ((heat - from).clamp(0.0, 1.0) * 255.0) as u8;
+        Color { red: ramp(0.0), green: ramp(1.0), blue: ramp(2.0) }
+    }
+}
+
+impl Effect for Fire {
+    // Fire2012: cool everything, let heat drift up the strip, spark at the bottom
+    fn render(&mut self, _elapsed: f32, dt: f32, frame: &mut [Color]) {
+        let rate = dt * self.speed * 30.0;
+        for (strip, heat) in strips(frame, &self.channels).zip(&mut self.heat) {
+            let len = heat.len();
+            if len == 0 {
+                continue;
+            }
+            for cell in heat.iter_mut() {
+                *cell = (*cell - self.rng.gen_range(0.0..0.06) * rate).max(0.0);
+            }
+            for i in (2..len).rev() {
+                heat[i] = (heat[i - 1] + 2.0 * heat[i - 2]) / 3.0;
+            }
+            if self.rng.gen_bool((0.5 * rate as f64).min(1.0)) {
+                let spark = self.rng.gen_range(0..len.min(3));
+                heat[spark] = (heat[spark] + self.rng.gen_range(0.6..0.9)).min(1.0);
+            }
+            for (pixel, &cell) in strip.iter_mut().zip(heat.iter()) {
+                *pixel = Fire::color(cell);
+            }
+        }
+    }
+}
+
+struct Twinkle {
+    speed: f32,
+    color: Color,
+    background: Color,
+    // How lit each pixel is, from 0 to 1
+    levels: Vec<f32>,
+    rng: StdRng,
+}
+
+impl Effect for Twinkle {
+    fn render(&mut self, _elapsed: f32, dt: f32, frame: &mut [Color]) {
+        let fade = dt * self.speed;
+        // About one new sparkle per ten pixels a second
+        let chance = (fade * 0.1) as f64;
+        for (pixel, level) in frame.iter_mut().zip(&mut self.levels) {
+            *level = (*level - fade).max(0.0);
+            if self.rng.gen_bool(chance.min(1.0)) {
+                *level = 1.0;
+            }
+            *pixel = self.background.mix(self.color, *level);
+        }
+    }
+}
+
+struct Larson {
+    speed: f32,
+    color: Color,
+    background: Color,
+    channels: Vec<usize>,
+}
+
+impl Effect for Larson {
+    fn render(&mut self, elapsed: f32, _dt: f32, frame: &mut [Color]) {
+        for strip in strips(frame, &self.channels) {
+            let last = strip.len().saturating_sub(1) as f32;
+            // There and back every two seconds
+            let phase = (elapsed * self.speed / 2.0).fract();
+            let eye = last * (1.0 - (2.0 * phase - 1.0).abs());
+            for (i, pixel) in strip.iter_mut().enumerate() {
+                let level = (1.0 - (i as f32 - eye).abs() / 2.0).max(0.0);
+                *pixel = self.background.mix(self.color, level);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lengths(frame: &mut [Color], channels: &[usize]) -> Vec<usize> {
+        strips(frame, channels).map(|strip| strip.len()).collect()
+    }
+
+    #[test]
+    fn splits_frames_into_channels() {
+        let mut frame = [Color::OFF; 7];
+        assert_eq!(lengths(&mut frame, &[4, 3]), [4, 3]);
+        assert_eq!(lengths(&mut frame, &[1, 0, 6]), [1, 0, 6]);
+        assert_eq!(lengths(&mut frame, &[0, 0]), [0, 0]);
+        assert_eq!(lengths(&mut frame, &[]), Vec::<usize>::new());
+        // A frame shorter than the channels runs out rather than panicking
+        assert_eq!(lengths(&mut frame, &[5, 5, 5]), [5, 2, 0]);
+
+        // Strips cover the frame in order
+        let mut frame = [Color::OFF; 5];
+        for (n, strip) in strips(&mut frame, &[2, 3]).enumerate() {
+            strip.fill(Color { red: n as u8, green: 0, blue: 0 });
+        }
+        let reds: Vec<u8> = frame.iter().map(|pixel| pixel.red).collect();
+        assert_eq!(reds, [0, 0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn renders_any_layout() {
+        let params = EffectParams::default();
+        for kind in EffectKind::ALL {
+            for channels in [&[][..], &[0], &[0, 3, 0], &[1], &[5, 2]] {
+                let mut effect = build(kind, &params, channels);
+                let mut frame = vec![Color::OFF; channels.iter().sum()];
+                for step in 0..50 {
+                    effect.render(step as f32 * 0.1, 0.1, &mut frame);
+                }
+            }
+        }
+    }
+}
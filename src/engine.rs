@@ -0,0 +1,482 @@
+use crate::effects::Effect;
+use crate::layers::{self, Overlay};
+use crate::rgb_controller::profiles::{Color, Easing, Transition, TransitionSpec};
+use crate::rgb_controller::{Capabilities, RgbController};
+use crate::Event;
+use anyhow::{anyhow, Result};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, oneshot, watch};
+
+// Dissolves and wipes without a duration take this long per pixel
+const DISSOLVE_STEP: Duration = Duration::from_millis(50);
+const DEFAULT_FADE: Duration = Duration::from_secs(1);
+// How often a running transition writes a frame, at most
+const FRAME_STEP: Duration = Duration::from_millis(20);
+// Transitions report their progress in steps of at least this much
+const PROGRESS_STEP: f32 = 0.01;
+
+#[derive(Debug, Clone)]
+pub struct EngineStatus {
+    pub controller: String,
+    /// Pixels per channel of the current controller.
+    pub channels: Vec<usize>,
+    pub capabilities: Capabilities,
+    pub connected: bool,
+    pub transitioning: bool,
+}
+
+enum Request {
+    Show {
+        frame: Vec<Color>,
+        transition: TransitionSpec,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    SetBrightness {
+        brightness: f32,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    Animate {
+        effect: Box<dyn Effect>,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    SetLayers {
+        overlays: Vec<Overlay>,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    SetPositions {
+        positions: Vec<Option<[f32; 2]>>,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    ReplaceController {
+        controller: Box<dyn RgbController>,
+        reply: oneshot::Sender<Result<()>>,
+    },
+}
+
+/// Handle to the render thread. The thread owns the controller, so callers
+/// never wait for a transition to finish; a new target replaces the old one
+/// at the next step.
+pub struct EngineHandle {
+    requests: mpsc::Sender<Request>,
+    status: watch::Receiver<EngineStatus>,
+}
+
+impl EngineHandle {
+    pub fn spawn(controller: Box<dyn RgbController>, events: broadcast::Sender<Event>) -> Self {
+        Self::start(controller, events, true)
+    }
+
+    /// Like `spawn`, but reports the device as disconnected until a write
+    /// succeeds or `replace_controller` is called.
+    pub fn spawn_disconnected(controller: Box<dyn RgbController>, events: broadcast::Sender<Event>) -> Self {
+        Self::start(controller, events, false)
+    }
+
+    fn start(controller: Box<dyn RgbController>, events: broadcast::Sender<Event>, connected: bool) -> Self {
+        let (requests, rx) = mpsc::channel();
+        let mut render = RenderLoop::new(controller, events);
+        let (status_tx, status) = watch::channel(EngineStatus {
+            controller: render.controller.name().to_string(),
+            channels: render.channels(),
+            capabilities: render.controller.capabilities(),
+            connected,
+            transitioning: false,
+        });
+
+        std::thread::Builder::new()
+            .name("rgbd-render".to_string())
+            .spawn(move || render.run(rx, status_tx))
+            .expect("failed to spawn render thread");
+
+        Self { requests, status }
+    }
+
+    /// Starts moving towards `frame`, which must match the controller's
+    /// pixel count. Returns once the first step has been written, so device
+    /// errors still reach the caller. Unset parts of `transition` get
+    /// defaults that suit the style.
+    pub async fn show(&self, frame: Vec<Color>, transition: TransitionSpec) -> Result<()> {
+        let (reply, response) = oneshot::channel();
+        self.send(Request::Show { frame, transition, reply })?;
+        response.await.map_err(|_| anyhow!("render thread stopped"))?
+    }
+
+    /// Runs `effect` until the next `show`. Returns once its first frame has
+    /// been written.
+    pub async fn animate(&self, effect: Box<dyn Effect>) -> Result<()> {
+        let (reply, response) = oneshot::channel();
+        self.send(Request::Animate { effect, reply })?;
+        response.await.map_err(|_| anyhow!("render thread stopped"))?
+    }
+
+    /// Scales everything written from now on, and rewrites what is showing.
+    /// Frames passed to `show` stay at full brightness.
+    pub async fn set_brightness(&self, brightness: f32) -> Result<()> {
+        let (reply, response) = oneshot::channel();
+        self.send(Request::SetBrightness { brightness, reply })?;
+        response.await.map_err(|_| anyhow!("render thread stopped"))?
+    }
+
+    /// Draws `overlays` over everything written from now on, replacing the
+    /// last set. Each one is dropped by the render thread once it expires.
+    pub async fn set_layers(&self, overlays: Vec<Overlay>) -> Result<()> {
+        let (reply, response) = oneshot::channel();
+        self.send(Request::SetLayers { overlays, reply })?;
+        response.await.map_err(|_| anyhow!("render thread stopped"))?
+    }
+
+    /// Places pixels in 2D, one entry per pixel, so wipes sweep across the
+    /// room rather than along the strip. Forgotten when the controller is
+    /// replaced.
+    pub async fn set_positions(&self, positions: Vec<Option<[f32; 2]>>) -> Result<()> {
+        let (reply, response) = oneshot::channel();
+        self.send(Request::SetPositions { positions, reply })?;
+        response.await.map_err(|_| anyhow!("render thread stopped"))?
+    }
+
+    pub async fn replace_controller(&self, controller: Box<dyn RgbController>) -> Result<()> {
+        let (reply, response) = oneshot::channel();
+        self.send(Request::ReplaceController { controller, reply })?;
+        response.await.map_err(|_| anyhow!("render thread stopped"))?
+    }
+
+    pub fn status(&self) -> EngineStatus {
+        self.status.borrow().clone()
+    }
+
+    fn send(&self, request: Request) -> Result<()> {
+        self.requests.send(request).map_err(|_| anyhow!("render thread stopped"))
+    }
+}
+
+// A transition on its way to `RenderLoop::target`
+struct Run {
+    style: Transition,
+    easing: Easing,
+    start: Instant,
+    duration: Duration,
+    from: Vec<Color>,
+    // The pixels that change, in the order dissolves and wipes flip them
+    order: Vec<usize>,
+    // The progress last sent out as an event
+    reported: f32,
+}
+
+struct RenderLoop {
+    controller: Box<dyn RgbController>,
+    events: broadcast::Sender<Event>,
+    // What is showing before brightness is applied
+    frame: Vec<Color>,
+    target: Vec<Color>,
+    brightness: f32,
+    // Drawn over `frame` on the way out, like brightness
+    overlays: Vec<Overlay>,
+    positions: Vec<Option<[f32; 2]>>,
+    run: Option<Run>,
+    effect: Option<Animation>,
+    rng: StdRng,
+}
+
+struct Animation {
+    effect: Box<dyn Effect>,
+    start: Instant,
+    last: Instant,
+}
+
+impl RenderLoop {
+    fn new(controller: Box<dyn RgbController>, events: broadcast::Sender<Event>) -> Self {
+        let mut render = Self {
+            controller,
+            events,
+            frame: Vec::new(),
+            target: Vec::new(),
+            brightness: 1.0,
+            overlays: Vec::new(),
+            positions: Vec::new(),
+            run: None,
+            effect: None,
+            rng: StdRng::from_entropy(),
+        };
+        render.reset_frame();
+        render
+    }
+
+    fn run(mut self, requests: mpsc::Receiver<Request>, status: watch::Sender<EngineStatus>) {
+        let mut next_step = Instant::now();
+        loop {
+            // Wake for the next step, or for the next layer to expire
+            let animating = self.run.is_some() || self.effect.is_some();
+            let expiry = self.overlays.iter().filter_map(|overlay| overlay.expires).min();
+            let deadline = match expiry {
+                Some(expiry) if animating => Some(expiry.min(next_step)),
+                Some(expiry) => Some(expiry),
+                None => animating.then_some(next_step),
+            };
+            let request = if let Some(deadline) = deadline {
+                let wait = deadline.saturating_duration_since(Instant::now());
+                match requests.recv_timeout(wait) {
+                    Ok(request) => Some(request),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            } else {
+                match requests.recv() {
+                    Ok(request) => Some(request),
+                    Err(_) => return,
+                }
+            };
+
+            let result = match request {
+                Some(Request::Show { frame, reply, .. }) if frame.len() != self.frame.len() => {
+                    let error = anyhow!("frame has {} pixels, controller has {}", frame.len(), self.frame.len());
+                    let _ = reply.send(Err(error));
+                    continue;
+                }
+                Some(Request::Show { frame, transition, reply }) => {
+                    self.effect = None;
+                    let result = self.retarget(frame, transition);
+                    let failed = result.is_err();
+                    let _ = reply.send(result);
+                    next_step = Instant::now() + self.step_interval();
+                    if failed { Err(()) } else { Ok(()) }
+                }
+                Some(Request::SetBrightness { brightness, reply }) => {
+                    self.brightness = brightness.clamp(0.0, 1.0);
+                    let result = self.write_frame(self.frame.clone());
+                    let failed = result.is_err();
+                    let _ = reply.send(result);
+                    if failed { Err(()) } else { Ok(()) }
+                }
+                Some(Request::Animate { effect, reply }) => {
+                    let now = Instant::now();
+                    self.run = None;
+                    self.effect = Some(Animation { effect, start: now, last: now });
+                    let result = self.step();
+                    let failed = result.is_err();
+                    let _ = reply.send(result);
+                    next_step = Instant::now() + self.step_interval();
+                    if failed { Err(()) } else { Ok(()) }
+                }
+                Some(Request::SetLayers { overlays, reply }) => {
+                    let now = Instant::now();
+                    self.overlays = overlays.into_iter().filter(|overlay| !overlay.is_expired(now)).collect();
+                    let result = self.write_frame(self.frame.clone());
+                    let failed = result.is_err();
+                    let _ = reply.send(result);
+                    if failed { Err(()) } else { Ok(()) }
+                }
+                Some(Request::SetPositions { positions, reply }) => {
+                    let result = if positions.len() == self.frame.len() {
+                        self.positions = positions;
+                        Ok(())
+                    } else {
+                        Err(anyhow!("{} positions for {} pixels", positions.len(), self.frame.len()))
+                    };
+                    // Nothing was written, so this says nothing about the connection
+                    let _ = reply.send(result);
+                    continue;
+                }
+                Some(Request::ReplaceController { controller, reply }) => {
+                    self.controller = controller;
+                    // Masks and positions were worked out for the old layout
+                    self.overlays.clear();
+                    self.positions.clear();
+                    self.reset_frame();
+                    status.send_modify(|status| {
+                        status.controller = self.controller.name().to_string();
+                        status.channels = self.channels();
+                        status.capabilities = self.controller.capabilities();
+                    });
+                    let _ = reply.send(Ok(()));
+                    Ok(())
+                }
+                None => {
+                    let mut result = self.expire_overlays();
+                    let animating = self.run.is_some() || self.effect.is_some();
+                    if result.is_ok() && animating && Instant::now() >= next_step {
+                        next_step += self.step_interval();
+                        result = self.step();
+                    }
+                    result.map_err(|e| eprintln!("Error during transition: {}", e))
+                }
+            };
+
+            if result.is_err() {
+                self.run = None;
+                self.effect = None;
+            }
+            self.publish_status(&status, result.is_ok());
+        }
+    }
+
+    fn publish_status(&self, status: &watch::Sender<EngineStatus>, connected: bool) {
+        let transitioning = self.run.is_some();
+        let mut reconnected = None;
+        status.send_if_modified(|status| {
+            if status.connected != connected {
+                reconnected = Some(connected);
+            }
+            let changed = status.connected != connected || status.transitioning != transitioning;
+            status.connected = connected;
+            status.transitioning = transitioning;
+            changed
+        });
+        if let Some(connected) = reconnected {
+            let device = self.controller.name().to_string();
+            let _ = self.events.send(Event::ConnectionChanged { device, connected });
+        }
+    }
+
+    fn reset_frame(&mut self) {
+        // Pick up where an addressable controller left off
+        self.frame = match self.controller.as_addressable() {
+            Some(addressable) => addressable.frame().to_vec(),
+            None => vec![Color::OFF; self.channels().iter().sum()],
+        };
+        self.target = self.frame.clone();
+        self.run = None;
+        self.effect = None;
+    }
+
+    fn retarget(&mut self, frame: Vec<Color>, transition: TransitionSpec) -> Result<()> {
+        self.target = frame;
+        self.run = None;
+
+        // Only pixels that aren't already there need to move
+        let mut order: Vec<usize> = (0..self.frame.len())
+            .filter(|&i| self.frame[i] != self.target[i])
+            .collect();
+        if order.is_empty() {
+            return Ok(());
+        }
+
+        let style = transition.style.unwrap_or_default();
+        let duration = match style {
+            Transition::Instant => return self.write_frame(self.target.clone()),
+            Transition::Dissolve | Transition::Wipe => DISSOLVE_STEP * order.len() as u32,
+            Transition::Fade => DEFAULT_FADE,
+        };
+        match style {
+            Transition::Dissolve => order.shuffle(&mut self.rng),
+            // Placed pixels go left to right, then the rest along the strip
+            Transition::Wipe if !self.positions.is_empty() => {
+                order.sort_by(|&a, &b| match (self.positions[a], self.positions[b]) {
+                    (Some(a), Some(b)) => a[0].total_cmp(&b[0]).then(a[1].total_cmp(&b[1])),
+                    (a, b) => b.is_some().cmp(&a.is_some()),
+                });
+            }
+            _ => {}
+        }
+        self.run = Some(Run {
+            style,
+            // Pixel by pixel looks best at a steady pace
+            easing: transition.easing.unwrap_or(if style == Transition::Fade { Easing::EaseInOut } else { Easing::Linear }),
+            start: Instant::now(),
+            duration: transition.duration().unwrap_or(duration),
+            from: self.frame.clone(),
+            order,
+            reported: 0.0,
+        });
+        self.step()
+    }
+
+    fn step(&mut self) -> Result<()> {
+        if let Some(animation) = &mut self.effect {
+            let now = Instant::now();
+            let elapsed = (now - animation.start).as_secs_f32();
+            let dt = (now - animation.last).as_secs_f32();
+            animation.last = now;
+            let mut frame = self.frame.clone();
+            animation.effect.render(elapsed, dt, &mut frame);
+            self.target = frame.clone();
+            return if frame != self.frame { self.write_frame(frame) } else { Ok(()) };
+        }
+        let Some(run) = &self.run else {
+            return Ok(());
+        };
+        let elapsed = run.start.elapsed();
+        let finished = elapsed >= run.duration;
+        let progress = if finished {
+            1.0
+        } else {
+            run.easing.apply(elapsed.as_secs_f32() / run.duration.as_secs_f32())
+        };
+
+        let frame = if finished {
+            self.target.clone()
+        } else if run.style == Transition::Fade {
+            run.from.iter().zip(&self.target).map(|(from, to)| from.mix(*to, progress)).collect()
+        } else {
+            // The first pixel goes at once, so errors show up straight away
+            let flipped = (progress * run.order.len() as f32) as usize + 1;
+            let mut frame = run.from.clone();
+            for &index in &run.order[..flipped.min(run.order.len())] {
+                frame[index] = self.target[index];
+            }
+            frame
+        };
+        let report = finished || progress - run.reported >= PROGRESS_STEP;
+        if finished {
+            self.run = None;
+        } else if let Some(run) = self.run.as_mut().filter(|_| report) {
+            run.reported = progress;
+        }
+        // Dissolves and wipes sit still between pixels
+        if frame != self.frame {
+            self.write_frame(frame)?;
+        }
+        if report {
+            let _ = self.events.send(Event::TransitionProgress(progress));
+        }
+        Ok(())
+    }
+
+    // Rewrites the frame without layers whose time is up
+    fn expire_overlays(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let before = self.overlays.len();
+        self.overlays.retain(|overlay| !overlay.is_expired(now));
+        if self.overlays.len() == before {
+            return Ok(());
+        }
+        self.write_frame(self.frame.clone())
+    }
+
+    // Never step faster than the device can take frames
+    fn step_interval(&self) -> Duration {
+        match self.controller.capabilities().max_refresh_rate {
+            Some(rate) if rate > 0 => FRAME_STEP.max(Duration::from_secs(1) / rate),
+            _ => FRAME_STEP,
+        }
+    }
+
+    // Controllers without per-pixel access are a single pixel
+    fn channels(&mut self) -> Vec<usize> {
+        match self.controller.as_addressable() {
+            Some(addressable) => addressable.layout(),
+            None => vec![1],
+        }
+    }
+
+    // Layers and brightness are applied here, on the way out, so transitions
+    // and effects carry on underneath and colors keep their hue
+    fn write_frame(&mut self, frame: Vec<Color>) -> Result<()> {
+        let brightness = self.brightness;
+        let output: Vec<Color> = layers::composite(&frame, &self.overlays).iter()
+            .map(|color| color.scale(brightness))
+            .collect();
+        match self.controller.as_addressable() {
+            Some(addressable) => addressable.write_frame(&output)?,
+            None => {
+                let color = output[0];
+                self.controller.set_color(color.red, color.green, color.blue)?;
+            }
+        }
+        self.frame = frame;
+        Ok(())
+    }
+}
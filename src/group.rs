@@ -0,0 +1,89 @@
+use crate::engine::EngineHandle;
+use crate::rgb_controller::factory::ControllerConfig;
+use crate::rgb_controller::RgbController;
+use crate::Event;
+use anyhow::{bail, Result};
+use tokio::sync::broadcast;
+
+/// One configured controller and the render thread driving it.
+pub struct Device {
+    pub name: String,
+    pub config: ControllerConfig,
+    pub engine: EngineHandle,
+}
+
+impl Device {
+    /// Reopens the backend from its config and hands it to the render thread.
+    pub async fn reconnect(&self) -> Result<()> {
+        let controller = self.config.build()?;
+        self.engine.replace_controller(controller).await
+    }
+}
+
+/// Every device the daemon drives. Each has its own render thread, so a slow
+/// or failing device never holds up the others.
+pub struct DeviceGroup {
+    devices: Vec<Device>,
+}
+
+impl DeviceGroup {
+    pub fn open(configs: &[ControllerConfig], events: &broadcast::Sender<Event>) -> Result<Self> {
+        if configs.is_empty() {
+            bail!("no controllers configured");
+        }
+        let mut devices: Vec<Device> = Vec::with_capacity(configs.len());
+        for config in configs {
+            let name = config.device_name();
+            if devices.iter().any(|device| device.name.eq_ignore_ascii_case(&name)) {
+                bail!("two controllers are named {:?}; give one a different `name`", name);
+            }
+            // A device that fails to open waits for `Reconnect` rather than
+            // keeping the others from starting
+            let engine = match config.build() {
+                Ok(controller) => EngineHandle::spawn(controller, events.clone()),
+                Err(e) => {
+                    eprintln!("[{}] Failed to open controller: {:#}", name, e);
+                    let controller = Box::new(Unopened { name: name.clone() });
+                    EngineHandle::spawn_disconnected(controller, events.clone())
+                }
+            };
+            devices.push(Device { name, config: config.clone(), engine });
+        }
+        Ok(Self { devices })
+    }
+
+    pub fn devices(&self) -> &[Device] {
+        &self.devices
+    }
+
+    /// The devices named in `targets`, ignoring case, or all of them if
+    /// `targets` is empty. Fails with the first name that matches nothing.
+    pub fn select(&self, targets: &[String]) -> std::result::Result<Vec<&Device>, String> {
+        if targets.is_empty() {
+            return Ok(self.devices.iter().collect());
+        }
+        targets.iter()
+            .map(|target| {
+                self.devices.iter()
+                    .find(|device| device.name.eq_ignore_ascii_case(target))
+                    .ok_or_else(|| target.clone())
+            })
+            .collect()
+    }
+}
+
+/// Stands in for a backend that failed to open, until `Device::reconnect`
+/// replaces it.
+struct Unopened {
+    name: String,
+}
+
+impl RgbController for Unopened {
+    fn set_color(&mut self, _red: u8, _green: u8, _blue: u8) -> Result<()> {
+        bail!("{} is not connected", self.name)
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
@@ -0,0 +1,171 @@
+use crate::rgb_controller::profiles::Color;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// How a layer combines with what is under it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BlendMode {
+    #[default]
+    Replace,
+    /// Adds the layer's color, scaled by its opacity.
+    Add,
+    /// Tints what is under the layer, e.g. darkens it with grey.
+    Multiply,
+    /// Mixes in the layer's color by its opacity.
+    Alpha,
+}
+
+fn opaque() -> f32 {
+    1.0
+}
+
+/// A temporary visual drawn over whatever the devices show, such as a
+/// notification flash or a recording light. Pushed with `Command::PushLayer`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Layer {
+    /// Pushing a layer with the same id replaces it.
+    pub id: String,
+    pub color: Color,
+    /// Higher priorities are drawn on top.
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default)]
+    pub blend: BlendMode,
+    /// Used by `add` and `alpha`, from 0 to 1.
+    #[serde(default = "opaque")]
+    pub opacity: f32,
+    /// Removes the layer by itself after this long.
+    #[serde(default)]
+    pub ttl_ms: Option<u64>,
+    /// Zones the layer covers; the whole device when empty.
+    #[serde(default)]
+    pub zones: Vec<String>,
+}
+
+impl Layer {
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl_ms.map(Duration::from_millis)
+    }
+
+    fn blend(&self, under: Color) -> Color {
+        match self.blend {
+            BlendMode::Replace => self.color,
+            BlendMode::Add => {
+                let over = self.color.scale(self.opacity);
+                Color {
+                    red: under.red.saturating_add(over.red),
+                    green: under.green.saturating_add(over.green),
+                    blue: under.blue.saturating_add(over.blue),
+                }
+            }
+            BlendMode::Multiply => {
+                let channel = |under: u8, over: u8| (under as u16 * over as u16 / 255) as u8;
+                Color {
+                    red: channel(under.red, self.color.red),
+                    green: channel(under.green, self.color.green),
+                    blue: channel(under.blue, self.color.blue),
+                }
+            }
+            BlendMode::Alpha => under.mix(self.color, self.opacity.clamp(0.0, 1.0)),
+        }
+    }
+}
+
+/// A layer as one device's render thread draws it.
+#[derive(Debug, Clone)]
+pub struct Overlay {
+    pub layer: Layer,
+    /// Frame indices the layer covers; every pixel when `None`.
+    pub mask: Option<Vec<usize>>,
+    pub expires: Option<Instant>,
+}
+
+impl Overlay {
+    pub fn is_expired(&self, now: Instant) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+/// Draws `overlays`, lowest priority first, over `frame`.
+pub fn composite(frame: &[Color], overlays: &[Overlay]) -> Vec<Color> {
+    let mut output = frame.to_vec();
+    let mut ordered: Vec<&Overlay> = overlays.iter().collect();
+    ordered.sort_by_key(|overlay| overlay.layer.priority);
+    for overlay in ordered {
+        match &overlay.mask {
+            Some(mask) => {
+                for &index in mask {
+                    if let Some(pixel) = output.get_mut(index) {
+                        *pixel = overlay.layer.blend(*pixel);
+                    }
+                }
+            }
+            None => {
+                for pixel in output.iter_mut() {
+                    *pixel = overlay.layer.blend(*pixel);
+                }
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GREY: Color = Color { red: 128, green: 128, blue: 128 };
+
+    fn rgb(red: u8, green: u8, blue: u8) -> Color {
+        Color { red, green, blue }
+    }
+
+    fn overlay(color: Color, blend: BlendMode, opacity: f32, priority: i32, mask: Option<Vec<usize>>) -> Overlay {
+        let layer = Layer { id: String::new(), color, priority, blend, opacity, ttl_ms: None, zones: Vec::new() };
+        Overlay { layer, mask, expires: None }
+    }
+
+    fn blend(under: Color, over: Color, blend: BlendMode, opacity: f32) -> Color {
+        composite(&[under], &[overlay(over, blend, opacity, 0, None)])[0]
+    }
+
+    #[test]
+    fn blends() {
+        let under = rgb(200, 100, 0);
+        assert_eq!(blend(under, GREY, BlendMode::Replace, 0.2), GREY);
+
+        assert_eq!(blend(under, rgb(100, 100, 100), BlendMode::Add, 1.0), rgb(255, 200, 100));
+        assert_eq!(blend(under, rgb(100, 100, 100), BlendMode::Add, 0.0), under);
+
+        assert_eq!(blend(under, GREY, BlendMode::Multiply, 1.0), rgb(100, 50, 0));
+        assert_eq!(blend(under, Color::OFF, BlendMode::Multiply, 1.0), Color::OFF);
+
+        assert_eq!(blend(under, GREY, BlendMode::Alpha, 0.0), under);
+        assert_eq!(blend(under, GREY, BlendMode::Alpha, 1.0), GREY);
+        assert_eq!(blend(under, GREY, BlendMode::Alpha, 5.0), GREY);
+    }
+
+    #[test]
+    fn draws_higher_priorities_on_top() {
+        let red = overlay(rgb(255, 0, 0), BlendMode::Replace, 1.0, 10, None);
+        let blue = overlay(rgb(0, 0, 255), BlendMode::Replace, 1.0, -10, None);
+        assert_eq!(composite(&[Color::OFF], &[red.clone(), blue.clone()]), [rgb(255, 0, 0)]);
+        assert_eq!(composite(&[Color::OFF], &[blue, red]), [rgb(255, 0, 0)]);
+
+        // Order matters once blends stop replacing
+        let dim = overlay(GREY, BlendMode::Multiply, 1.0, 1, None);
+        let white = overlay(rgb(255, 255, 255), BlendMode::Replace, 1.0, 0, None);
+        assert_eq!(composite(&[Color::OFF], &[dim, white]), [rgb(128, 128, 128)]);
+    }
+
+    #[test]
+    fn masks_limit_layers_to_their_pixels() {
+        let frame = [Color::OFF; 4];
+        let red = overlay(rgb(255, 0, 0), BlendMode::Replace, 1.0, 0, Some(vec![1, 3, 9]));
+        assert_eq!(composite(&frame, &[red]), [Color::OFF, rgb(255, 0, 0), Color::OFF, rgb(255, 0, 0)]);
+
+        let empty = overlay(rgb(255, 0, 0), BlendMode::Replace, 1.0, 0, Some(Vec::new()));
+        assert_eq!(composite(&frame, &[empty]), frame);
+    }
+}
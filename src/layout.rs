@@ -0,0 +1,240 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+/// A named run of pixels on one channel, from the `[[zone]]` tables of the
+/// config file. Several entries with the same name make up one zone, e.g. a
+/// `desk` zone spanning two channels.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Zone {
+    pub name: String,
+    /// Device the pixels are on; every addressable device when unset.
+    #[serde(default)]
+    pub device: Option<String>,
+    /// 1-based, like the built-in `channel-N` zones.
+    pub channel: usize,
+    #[serde(default)]
+    pub start: usize,
+    /// One past the last pixel; the end of the channel when unset.
+    #[serde(default)]
+    pub end: Option<usize>,
+    /// The strip runs from `end` back to `start`.
+    #[serde(default)]
+    pub reverse: bool,
+    /// Where the first and last pixel sit, in any 2D units you like.
+    #[serde(default)]
+    pub from: Option<[f32; 2]>,
+    #[serde(default)]
+    pub to: Option<[f32; 2]>,
+}
+
+impl Zone {
+    fn on_device(&self, device: &str) -> bool {
+        self.device.as_deref().is_none_or(|name| name.eq_ignore_ascii_case(device))
+    }
+
+    /// Frame indices of the zone's pixels, in strip order.
+    fn indices(&self, channels: &[usize]) -> Result<Vec<usize>> {
+        let Some(&len) = channels.get(self.channel.wrapping_sub(1)) else {
+            bail!("zone {:?} uses channel {}, but the device has {}", self.name, self.channel, channels.len());
+        };
+        let end = self.end.unwrap_or(len);
+        if self.start >= end || end > len {
+            bail!("zone {:?} covers pixels {}..{}, but channel {} has {}", self.name, self.start, end, self.channel, len);
+        }
+        let offset: usize = channels[..self.channel - 1].iter().sum();
+        let mut indices: Vec<usize> = (offset + self.start..offset + end).collect();
+        if self.reverse {
+            indices.reverse();
+        }
+        Ok(indices)
+    }
+}
+
+/// Every zone from the config. Besides these, `channel-1`, `channel-2`, ...
+/// always name a whole channel of every addressable device.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Layout {
+    zones: Vec<Zone>,
+}
+
+impl Layout {
+    pub fn contains(&self, zone: &str) -> bool {
+        self.canonical_name(zone).is_some()
+    }
+
+    /// The zone's name as configured, since lookups ignore case.
+    pub fn canonical_name(&self, zone: &str) -> Option<String> {
+        if let Some(channel) = builtin_channel(zone) {
+            return Some(format!("channel-{}", channel));
+        }
+        self.entries(zone).next().map(|entry| entry.name.clone())
+    }
+
+    /// Frame indices of `zone` on `device`, in strip order. Empty if the zone
+    /// lives on other devices, or names a channel this device doesn't have.
+    pub fn pixels(&self, zone: &str, device: &str, channels: &[usize]) -> Result<Vec<usize>> {
+        if let Some(channel) = builtin_channel(zone) {
+            if channel > channels.len() {
+                return Ok(Vec::new());
+            }
+            let whole = Zone {
+                name: zone.to_string(),
+                device: None,
+                channel,
+                start: 0,
+                end: None,
+                reverse: false,
+                from: None,
+                to: None,
+            };
+            return whole.indices(channels);
+        }
+        if !self.contains(zone) {
+            bail!("unknown zone {:?}", zone);
+        }
+        let mut pixels = Vec::new();
+        for entry in self.entries(zone).filter(|entry| entry.on_device(device)) {
+            pixels.extend(entry.indices(channels)?);
+        }
+        Ok(pixels)
+    }
+
+    /// 2D position of every pixel of `device`, for zones that set `from` and
+    /// `to`. Pixels are spread evenly between the two, in strip order.
+    pub fn positions(&self, device: &str, channels: &[usize]) -> Vec<Option<[f32; 2]>> {
+        let mut positions = vec![None; channels.iter().sum()];
+        for entry in self.zones.iter().filter(|entry| entry.on_device(device)) {
+            let (Some(from), Some(to)) = (entry.from, entry.to) else {
+                continue;
+            };
+            let Ok(indices) = entry.indices(channels) else {
+                continue;
+            };
+            let steps = (indices.len() - 1).max(1) as f32;
+            for (i, index) in indices.into_iter().enumerate() {
+                let t = i as f32 / steps;
+                positions[index] = Some([from[0] + (to[0] - from[0]) * t, from[1] + (to[1] - from[1]) * t]);
+            }
+        }
+        positions
+    }
+
+    /// Checks what can be checked without a device: ranges that can never
+    /// fit and zones on devices that aren't configured.
+    pub fn validate(&self, devices: &[String]) -> Result<()> {
+        for zone in &self.zones {
+            if zone.channel == 0 {
+                bail!("zone {:?}: channels are numbered from 1", zone.name);
+            }
+            if zone.end.is_some_and(|end| end <= zone.start) {
+                bail!("zone {:?} ends before it starts", zone.name);
+            }
+            if let Some(device) = &zone.device {
+                if !devices.iter().any(|name| name.eq_ignore_ascii_case(device)) {
+                    bail!("zone {:?} is on unknown device {:?}", zone.name, device);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn entries<'a>(&'a self, zone: &'a str) -> impl Iterator<Item = &'a Zone> {
+        self.zones.iter().filter(move |entry| entry.name.eq_ignore_ascii_case(zone))
+    }
+}
+
+// Like every zone name, `channel-N` ignores case
+fn builtin_channel(zone: &str) -> Option<usize> {
+    const PREFIX: &str = "channel-";
+    zone.get(..PREFIX.len())
+        .filter(|prefix| prefix.eq_ignore_ascii_case(PREFIX))
+        .and_then(|_| zone[PREFIX.len()..].parse::<usize>().ok())
+        .filter(|&n| n > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(toml: &str) -> Layout {
+        #[derive(Deserialize)]
+        struct File {
+            zone: Layout,
+        }
+        toml::from_str::<File>(toml).unwrap().zone
+    }
+
+    const DESK: &str = r#"
+        [[zone]]
+        name = "desk"
+        channel = 1
+        start = 2
+        [[zone]]
+        name = "desk"
+        channel = 2
+        end = 3
+        reverse = true
+        [[zone]]
+        name = "shelf"
+        device = "Shelf"
+        channel = 1
+        from = [0.0, 0.0]
+        to = [4.0, 2.0]
+    "#;
+
+    #[test]
+    fn finds_pixels() {
+        let layout = layout(DESK);
+        let channels = [4, 4];
+        // Entries add up, with the reversed run from its end back
+        assert_eq!(layout.pixels("Desk", "Monitors", &channels).unwrap(), [2, 3, 6, 5, 4]);
+        assert_eq!(layout.canonical_name("DESK").as_deref(), Some("desk"));
+        // Zones on other devices are empty here
+        assert_eq!(layout.pixels("shelf", "Monitors", &channels).unwrap(), Vec::<usize>::new());
+        assert_eq!(layout.pixels("shelf", "shelf", &channels).unwrap(), [0, 1, 2, 3]);
+        assert!(layout.pixels("lamp", "Monitors", &channels).is_err());
+        // Runs that don't fit the device are errors
+        assert!(layout.pixels("desk", "Monitors", &[4]).is_err());
+        assert!(layout.pixels("desk", "Monitors", &[2, 4]).is_err());
+    }
+
+    #[test]
+    fn names_whole_channels() {
+        let layout = Layout::default();
+        let channels = [2, 3];
+        assert_eq!(layout.pixels("channel-2", "Monitors", &channels).unwrap(), [2, 3, 4]);
+        assert_eq!(layout.pixels("Channel-1", "Monitors", &channels).unwrap(), [0, 1]);
+        assert_eq!(layout.canonical_name("CHANNEL-02").as_deref(), Some("channel-2"));
+        // Channels the device doesn't have are empty rather than errors
+        assert_eq!(layout.pixels("channel-3", "Monitors", &channels).unwrap(), Vec::<usize>::new());
+        for zone in ["channel-0", "channel-", "channel-x", "channels-1", "chan"] {
+            assert!(!layout.contains(zone), "{:?}", zone);
+        }
+    }
+
+    #[test]
+    fn spreads_positions_along_runs() {
+        let desk = layout(DESK);
+        let positions = desk.positions("Shelf", &[5, 4]);
+        assert_eq!(positions.len(), 9);
+        assert_eq!(positions[0], Some([0.0, 0.0]));
+        assert_eq!(positions[1], Some([1.0, 0.5]));
+        assert_eq!(positions[4], Some([4.0, 2.0]));
+        assert!(positions[5..].iter().all(Option::is_none));
+
+        let reversed = layout(r#"
+            [[zone]]
+            name = "back"
+            channel = 2
+            start = 1
+            reverse = true
+            from = [0.0, 0.0]
+            to = [2.0, 0.0]
+        "#);
+        let positions = reversed.positions("Monitors", &[4, 4]);
+        assert_eq!(&positions[4..], [None, Some([2.0, 0.0]), Some([1.0, 0.0]), Some([0.0, 0.0])]);
+        // Devices the zones aren't on get no positions
+        assert!(desk.positions("Monitors", &[4, 4]).iter().all(Option::is_none));
+    }
+}
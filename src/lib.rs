@@ -1,26 +1,186 @@
 pub mod rgb_controller;
+pub mod protocol;
+pub mod client;
+pub mod config;
+pub mod engine;
+pub mod persist;
+pub mod group;
+pub mod layout;
+pub mod effects;
+pub mod layers;
+pub mod schedule;
+pub mod solar;
+pub mod circadian;
 
 use serde::{Deserialize, Serialize};
-pub use rgb_controller::profiles::{Profile, Color, ColorSetting};
+use std::collections::BTreeMap;
+use effects::EffectParams;
+use layers::Layer;
+use schedule::ScheduleStatus;
+use circadian::CurvePosition;
+pub use rgb_controller::profiles::{Profile, Color, ColorSetting, Easing, Transition, TransitionSpec};
 
+/// Deserializes from anything `Color` does, so `{"SetColor": "#ff8800"}` works.
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(from = "Color")]
 pub struct RgbCommand {
     pub red: u8,
     pub green: u8,
     pub blue: u8,
 }
 
+impl From<Color> for RgbCommand {
+    fn from(Color { red, green, blue }: Color) -> Self {
+        Self { red, green, blue }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Command {
     SetColor(RgbCommand),
     SetProfile(ColorSetting),
+    /// Paint one zone of the layout over whatever is showing.
+    SetZone { zone: String, color: Color },
+    /// Start an animated effect from `effects`, by name.
+    SetEffect {
+        name: String,
+        #[serde(default)]
+        params: EffectParams,
+    },
+    /// Global brightness from 0 to 1, applied on top of whatever is showing.
+    SetBrightness(f32),
+    /// Draw a layer over whatever is showing, replacing any with the same id.
+    PushLayer(Layer),
+    /// Remove a layer, bringing back what was under it.
+    PopLayer { id: String },
+    GetSchedule,
+    /// Stop the schedule for `duration_ms`, or until resumed.
+    PauseSchedule { duration_ms: Option<u64> },
+    /// Undo a pause or a manual override, so the next rule runs on time.
+    ResumeSchedule,
     Reconnect,
+    GetState,
+    /// Keep the connection open and stream an `Event` for every state change.
+    Subscribe,
+    /// Apply `SetColor`, `SetProfile`, `SetZone`, `PushLayer` or `Reconnect` to the named devices only.
+    Target { devices: Vec<String>, command: Box<Command> },
+    /// Run `SetColor`, `SetProfile` or `SetZone` with this transition instead
+    /// of the one the setting or config picks.
+    Transition { transition: TransitionSpec, command: Box<Command> },
+}
+
+impl Command {
+    /// Restricts the command to `devices`; an empty list means every device.
+    pub fn targeted(self, devices: Vec<String>) -> Command {
+        if devices.is_empty() {
+            self
+        } else {
+            Command::Target { devices, command: Box::new(self) }
+        }
+    }
+
+    /// Overrides the transition; an empty spec leaves the command as it is.
+    pub fn with_transition(self, transition: TransitionSpec) -> Command {
+        if transition.is_empty() {
+            self
+        } else {
+            Command::Transition { transition, command: Box::new(self) }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// The frame could not be parsed as a `Command`.
+    InvalidRequest,
+    /// The frame was larger than `protocol::MAX_FRAME_LEN`.
+    FrameTooLarge,
+    /// The controller rejected the request or is disconnected.
+    DeviceError,
+    /// The active controller can't perform this command.
+    Unsupported,
+    /// No profile with the requested name is configured.
+    UnknownProfile,
+    /// A targeted command named a device the daemon doesn't drive.
+    UnknownDevice,
+    /// No zone with the requested name is configured.
+    UnknownZone,
+    /// No built-in effect has the requested name.
+    UnknownEffect,
+    /// No layer with the requested id is on the stack.
+    UnknownLayer,
+}
+
+/// One device's share of a failed command. Devices not listed succeeded.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeviceFailure {
+    pub device: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Response {
+    Ok,
+    Error {
+        code: ErrorCode,
+        message: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        failures: Vec<DeviceFailure>,
+    },
+    State(Box<DaemonStatus>),
+    Schedule(ScheduleStatus),
+    Event(Event),
+}
+
+/// Pushed to subscribers after `Command::Subscribe` has been acknowledged.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Event {
+    ColorChanged(RgbCommand),
+    ProfileChanged(ColorSetting),
+    ZoneChanged { zone: String, color: Color },
+    ConnectionChanged {
+        #[serde(default)]
+        device: String,
+        connected: bool,
+    },
+    /// Fraction of the pixels that have reached the target, from 0.0 to 1.0.
+    /// Sent in steps of at least 1%, and always at 1.0.
+    TransitionProgress(f32),
+    BrightnessChanged(f32),
+    LayerPushed(String),
+    LayerPopped(String),
+}
+
+impl Response {
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        Response::Error { code, message: message.into(), failures: Vec::new() }
+    }
+
+    /// Error for a command that failed on some of the devices it was sent to.
+    pub fn device_failures(failures: Vec<DeviceFailure>) -> Self {
+        let message = failures.iter()
+            .map(|failure| format!("{}: {}", failure.device, failure.message))
+            .collect::<Vec<_>>()
+            .join("; ");
+        Response::Error { code: ErrorCode::DeviceError, message, failures }
+    }
+}
+
+fn full_brightness() -> f32 {
+    1.0
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct MoteState {
     pub current_profile: Option<ColorSetting>,
     pub last_color: Option<RgbCommand>,
+    /// Devices that were last set on their own, overriding the above.
+    pub devices: BTreeMap<String, ColorSetting>,
+    /// Zones painted with `SetZone` since, drawn over the device settings.
+    pub zones: BTreeMap<String, Color>,
+    /// Scales everything the devices show, from 0 to 1.
+    pub brightness: f32,
 }
 
 impl Default for MoteState {
@@ -28,10 +188,74 @@ impl Default for MoteState {
         Self {
             current_profile: None,
             last_color: None,
+            devices: BTreeMap::new(),
+            zones: BTreeMap::new(),
+            brightness: full_brightness(),
         }
     }
 }
 
+impl MoteState {
+    /// What `device` should be showing.
+    pub fn setting_for(&self, device: &str) -> Option<ColorSetting> {
+        self.devices.get(device).cloned().or_else(|| self.active_setting())
+    }
+
+    /// The setting currently shown on the device, whichever way it was applied.
+    pub fn active_setting(&self) -> Option<ColorSetting> {
+        self.current_profile.clone().or_else(|| {
+            self.last_color.as_ref().map(|rgb| ColorSetting::Custom(Color {
+                red: rgb.red,
+                green: rgb.green,
+                blue: rgb.blue,
+            }))
+        })
+    }
+}
+
+/// Snapshot returned for `Command::GetState`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DaemonStatus {
+    pub setting: Option<ColorSetting>,
+    pub last_color: Option<RgbCommand>,
+    pub controller: String,
+    pub connected: bool,
+    pub transitioning: bool,
+    #[serde(default)]
+    pub devices: Vec<DeviceStatus>,
+    #[serde(default)]
+    pub zones: BTreeMap<String, Color>,
+    #[serde(default = "full_brightness")]
+    pub brightness: f32,
+    /// The layer stack, lowest priority first.
+    #[serde(default)]
+    pub layers: Vec<LayerStatus>,
+    /// Where on the circadian curve we are, while any device follows it.
+    #[serde(default)]
+    pub circadian: Option<CurvePosition>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LayerStatus {
+    #[serde(flatten)]
+    pub layer: Layer,
+    /// Devices the layer was pushed to; every device when empty.
+    #[serde(default)]
+    pub devices: Vec<String>,
+    /// Time left before the layer removes itself.
+    pub expires_in_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeviceStatus {
+    pub name: String,
+    pub setting: Option<ColorSetting>,
+    pub connected: bool,
+    pub transitioning: bool,
+    #[serde(default)]
+    pub capabilities: Capabilities,
+}
+
 // Re-export everything needed by the binary
-pub use rgb_controller::RgbController;
+pub use rgb_controller::{Capabilities, RgbController};
 pub use rgb_controller::mote::MoteController; 
\ No newline at end of file
@@ -1,14 +1,29 @@
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::net::UnixListener;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::io::BufReader;
+use tokio::sync::{broadcast, Mutex, Notify};
+use chrono::{DateTime, Local};
 use rgb_daemon::{
-    Command, RgbCommand, Profile, ColorSetting,
-    RgbController, MoteController, MoteState
+    Command, Color, ColorSetting, Easing, Transition, TransitionSpec, Response, ErrorCode, DaemonStatus, DeviceStatus,
+    DeviceFailure, Event, LayerStatus, MoteState, RgbCommand
 };
+use rgb_daemon::effects::{self, EffectKind, EffectParams};
+use rgb_daemon::engine::EngineStatus;
+use rgb_daemon::layers::{BlendMode, Layer, Overlay};
+use rgb_daemon::schedule::{RuleStatus, ScheduleStatus, Span};
+use rgb_daemon::solar::SolarEvent;
+use rgb_daemon::circadian::CurvePosition;
+use rgb_daemon::rgb_controller::profiles::{MAX_KELVIN, MIN_KELVIN};
+use rgb_daemon::group::{Device, DeviceGroup};
+use rgb_daemon::config::Config;
+use rgb_daemon::rgb_controller::factory::{Backend, ControllerConfig};
+use rgb_daemon::persist;
+use rgb_daemon::client::{RgbClient, DEFAULT_SOCKET_PATH};
+use rgb_daemon::protocol::{self, FrameTooLarge};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -21,55 +36,747 @@ struct Cli {
 enum Commands {
     /// Start the RGB daemon
     Daemon {
-        #[arg(short, long, default_value = "/tmp/rgb-daemon.sock")]
+        #[arg(short, long, default_value = DEFAULT_SOCKET_PATH)]
         socket: PathBuf,
+        /// Config file to use instead of $XDG_CONFIG_HOME/rgbd/config.toml
+        #[arg(short, long)]
+        config: Option<PathBuf>,
+        /// Drive only this backend, ignoring the controllers in the config
+        #[arg(long, value_enum)]
+        controller: Option<Backend>,
     },
     /// Send a command to the daemon
     Set {
-        #[arg(short, long)]
-        red: u8,
-        #[arg(short, long)]
-        green: u8,
-        #[arg(short, long)]
-        blue: u8,
-        #[arg(short, long, default_value = "/tmp/rgb-daemon.sock")]
+        #[command(flatten)]
+        color: ColorArgs,
+        #[command(flatten)]
+        transition: TransitionArgs,
+        /// Only set these devices (repeatable); defaults to all of them
+        #[arg(short, long = "device")]
+        devices: Vec<String>,
+        #[arg(short, long, default_value = DEFAULT_SOCKET_PATH)]
         socket: PathBuf,
     },
-    /// Activate a preset profile
+    /// Activate a profile from the config file
     Profile {
-        #[arg(value_enum)]
-        profile: Profile,
-        #[arg(short, long, default_value = "/tmp/rgb-daemon.sock")]
+        profile: String,
+        #[command(flatten)]
+        transition: TransitionArgs,
+        /// Only switch these devices (repeatable); defaults to all of them
+        #[arg(short, long = "device")]
+        devices: Vec<String>,
+        #[arg(short, long, default_value = DEFAULT_SOCKET_PATH)]
+        socket: PathBuf,
+    },
+    /// Switch to white at a color temperature
+    Temp {
+        /// In Kelvin, e.g. 2700 for warm or 6500 for daylight
+        #[arg(value_parser = clap::value_parser!(u32).range(MIN_KELVIN as i64..=MAX_KELVIN as i64))]
+        kelvin: u32,
+        #[command(flatten)]
+        transition: TransitionArgs,
+        /// Only switch these devices (repeatable); defaults to all of them
+        #[arg(short, long = "device")]
+        devices: Vec<String>,
+        #[arg(short, long, default_value = DEFAULT_SOCKET_PATH)]
+        socket: PathBuf,
+    },
+    /// Run an animated effect
+    Effect {
+        name: EffectKind,
+        /// 1 is the effect's natural pace, 2 twice as fast
+        #[arg(long, default_value_t = 1.0)]
+        speed: f32,
+        /// Main color, in any format `rgbd set` takes
+        #[arg(long)]
+        color: Option<Color>,
+        /// Color of the unlit pixels
+        #[arg(long)]
+        background: Option<Color>,
+        /// Only run it on these devices (repeatable); defaults to all of them
+        #[arg(short, long = "device")]
+        devices: Vec<String>,
+        #[arg(short, long, default_value = DEFAULT_SOCKET_PATH)]
+        socket: PathBuf,
+    },
+    /// Follow the sun with color temperature and brightness, through the day
+    Circadian {
+        #[command(flatten)]
+        transition: TransitionArgs,
+        /// Only switch these devices (repeatable); defaults to all of them
+        #[arg(short, long = "device")]
+        devices: Vec<String>,
+        #[arg(short, long, default_value = DEFAULT_SOCKET_PATH)]
+        socket: PathBuf,
+    },
+    /// Set the global brightness: `50%`, or `+10%`/`-10%` relative to now
+    Brightness {
+        #[arg(allow_hyphen_values = true)]
+        level: BrightnessLevel,
+        #[arg(short, long, default_value = DEFAULT_SOCKET_PATH)]
+        socket: PathBuf,
+    },
+    /// Paint one zone of the layout
+    Zone {
+        zone: String,
+        #[command(flatten)]
+        color: ColorArgs,
+        #[command(flatten)]
+        transition: TransitionArgs,
+        /// Only paint the zone on these devices (repeatable)
+        #[arg(short, long = "device")]
+        devices: Vec<String>,
+        #[arg(short, long, default_value = DEFAULT_SOCKET_PATH)]
+        socket: PathBuf,
+    },
+    /// Draw temporary layers over what is showing
+    Layer {
+        #[command(subcommand)]
+        action: LayerAction,
+    },
+    /// Inspect or pause the schedule from the config file
+    Schedule {
+        #[command(subcommand)]
+        action: ScheduleAction,
+    },
+    /// Print state changes as they happen
+    Watch {
+        /// Print each event as a JSON line
+        #[arg(long)]
+        json: bool,
+        #[arg(short, long, default_value = DEFAULT_SOCKET_PATH)]
+        socket: PathBuf,
+    },
+    /// List the devices the daemon drives and what each can do
+    Devices {
+        /// Print the devices as JSON
+        #[arg(long)]
+        json: bool,
+        #[arg(short, long, default_value = DEFAULT_SOCKET_PATH)]
+        socket: PathBuf,
+    },
+    /// Show what the lights are currently doing
+    Status {
+        /// Print the raw state as JSON
+        #[arg(long)]
+        json: bool,
+        #[arg(short, long, default_value = DEFAULT_SOCKET_PATH)]
+        socket: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum LayerAction {
+    /// Push a layer, replacing any with the same id
+    Push {
+        id: String,
+        #[command(flatten)]
+        color: ColorArgs,
+        /// Higher priorities are drawn on top
+        #[arg(long, default_value_t = 0, allow_hyphen_values = true)]
+        priority: i32,
+        #[arg(long, value_enum, default_value_t = BlendMode::Replace)]
+        blend: BlendMode,
+        /// How much of the layer shows with `add` and `alpha`, from 0 to 1
+        #[arg(long, default_value_t = 1.0)]
+        opacity: f32,
+        /// Remove the layer after this many seconds
+        #[arg(long)]
+        ttl: Option<f32>,
+        /// Only cover this zone (repeatable); defaults to the whole device
+        #[arg(short, long = "zone")]
+        zones: Vec<String>,
+        /// Only draw it on these devices (repeatable); defaults to all of them
+        #[arg(short, long = "device")]
+        devices: Vec<String>,
+        #[arg(short, long, default_value = DEFAULT_SOCKET_PATH)]
+        socket: PathBuf,
+    },
+    /// Remove a layer
+    Pop {
+        id: String,
+        #[arg(short, long, default_value = DEFAULT_SOCKET_PATH)]
+        socket: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum ScheduleAction {
+    /// List the rules and when each fires next
+    List {
+        #[arg(short, long, default_value = DEFAULT_SOCKET_PATH)]
+        socket: PathBuf,
+    },
+    /// Show the next rule to fire
+    Next {
+        #[arg(short, long, default_value = DEFAULT_SOCKET_PATH)]
+        socket: PathBuf,
+    },
+    /// Stop the schedule until resumed, or for a while, e.g. `--for 2h`
+    Pause {
+        #[arg(long = "for")]
+        duration: Option<Span>,
+        #[arg(short, long, default_value = DEFAULT_SOCKET_PATH)]
+        socket: PathBuf,
+    },
+    /// Undo a pause or a manual override
+    Resume {
+        #[arg(short, long, default_value = DEFAULT_SOCKET_PATH)]
         socket: PathBuf,
     },
 }
 
+/// A color as one argument (`orange`, `#ff8800`, `hsl(30, 100%, 50%)`,
+/// `2700K`, ...) or as separate `-r -g -b` bytes.
+#[derive(Args)]
+struct ColorArgs {
+    #[arg(required_unless_present_all = ["red", "green", "blue"], conflicts_with_all = ["red", "green", "blue"])]
+    color: Option<Color>,
+    #[arg(short, long, requires_all = ["green", "blue"])]
+    red: Option<u8>,
+    #[arg(short, long, requires_all = ["red", "blue"])]
+    green: Option<u8>,
+    #[arg(short, long, requires_all = ["red", "green"])]
+    blue: Option<u8>,
+}
+
+impl ColorArgs {
+    fn color(&self) -> Color {
+        self.color.unwrap_or(Color {
+            red: self.red.unwrap_or_default(),
+            green: self.green.unwrap_or_default(),
+            blue: self.blue.unwrap_or_default(),
+        })
+    }
+}
+
+/// Overrides for how the change is shown; the rest comes from the profile and config.
+#[derive(Args)]
+struct TransitionArgs {
+    #[arg(long, value_enum)]
+    transition: Option<Transition>,
+    /// In milliseconds
+    #[arg(long)]
+    duration: Option<u64>,
+    #[arg(long, value_enum)]
+    easing: Option<Easing>,
+}
+
+impl TransitionArgs {
+    fn spec(&self) -> TransitionSpec {
+        TransitionSpec { style: self.transition, duration_ms: self.duration, easing: self.easing }
+    }
+}
+
+/// `50%` or `0.5` sets the brightness, `+10%` and `-10%` change it.
+#[derive(Clone, Copy)]
+enum BrightnessLevel {
+    Absolute(f32),
+    Relative(f32),
+}
+
+impl std::str::FromStr for BrightnessLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (number, scale) = match s.strip_suffix('%') {
+            Some(percent) => (percent, 100.0),
+            None => (s, 1.0),
+        };
+        let value = number.parse::<f32>().map_err(|_| format!("{:?} is not a brightness like 50% or +10%", s))? / scale;
+        if s.starts_with(['+', '-']) {
+            Ok(BrightnessLevel::Relative(value))
+        } else if (0.0..=1.0).contains(&value) {
+            Ok(BrightnessLevel::Absolute(value))
+        } else {
+            Err(format!("brightness goes from 0% to 100%, not {}", s))
+        }
+    }
+}
+
+// Circadian mode moves to its next point on the curve this often; a minute
+// of the sun is a small enough step to fade over without being noticed
+const CIRCADIAN_STEP: Duration = Duration::from_secs(60);
+const CIRCADIAN_FADE: Duration = Duration::from_secs(2);
+
+// The schedule task wakes at least this often, in case the clock jumps
+const SCHEDULE_RECHECK: Duration = Duration::from_secs(60);
+
 struct DaemonState {
-    controller: Box<dyn RgbController>,
+    devices: DeviceGroup,
+    config: Config,
     state: MoteState,
+    // Temporary, so not part of the saved state
+    layers: Vec<ActiveLayer>,
+    // Wakes the expiry task when a layer with a ttl is pushed
+    layers_changed: Arc<Notify>,
+    schedule: ScheduleState,
+    events: broadcast::Sender<Event>,
+}
+
+struct ScheduleState {
+    // Rules due up to here have run, or been skipped
+    checked: DateTime<Local>,
+    paused: bool,
+    paused_until: Option<DateTime<Local>>,
+    // Set by manual changes, to hold the schedule off
+    override_until: Option<DateTime<Local>>,
+    // The last rule held off by an override, run once it ends
+    missed: Option<usize>,
+    // Wakes the schedule task early when any of the above changes
+    wake: Arc<Notify>,
+}
+
+struct ActiveLayer {
+    layer: Layer,
+    // Empty for every device
+    devices: Vec<String>,
+    expires: Option<Instant>,
+}
+
+enum ApplyError {
+    UnknownProfile(String),
+    Failed(anyhow::Error),
+}
+
+/// Outcome of a command sent to several devices.
+#[derive(Default)]
+struct Applied {
+    devices: Vec<String>,
+    failures: Vec<DeviceFailure>,
+}
+
+impl Applied {
+    fn any_succeeded(&self) -> bool {
+        self.failures.len() < self.devices.len()
+    }
+
+    fn fail(&mut self, device: &str, error: anyhow::Error) {
+        eprintln!("[{}] {:#}", device, error);
+        self.failures.push(DeviceFailure { device: device.to_string(), message: format!("{:#}", error) });
+    }
+
+    fn into_response(self) -> Response {
+        if self.failures.is_empty() {
+            Response::Ok
+        } else {
+            Response::device_failures(self.failures)
+        }
+    }
 }
 
 impl DaemonState {
-    fn new() -> Result<Self> {
+    fn new(config: Config) -> Result<Self> {
+        let (events, _) = broadcast::channel(64);
         Ok(Self {
-            controller: Box::new(MoteController::new("Pimoroni Mote".to_string())?),
+            devices: DeviceGroup::open(&config.controllers, &events)?,
+            config,
             state: MoteState::default(),
+            layers: Vec::new(),
+            layers_changed: Arc::new(Notify::new()),
+            schedule: ScheduleState {
+                checked: Local::now(),
+                paused: false,
+                paused_until: None,
+                override_until: None,
+                missed: None,
+                wake: Arc::new(Notify::new()),
+            },
+            events,
         })
     }
 
-    async fn restore_state(&mut self) -> Result<()> {
-        if let Some(color) = &self.state.last_color {
-            self.controller.set_color(color.red, color.green, color.blue)?;
-        } else if let Some(profile) = &self.state.current_profile {
-            if let Some(mote) = self.controller.as_any().downcast_mut::<MoteController>() {
-                mote.transition_to(profile.clone()).await?;
+    /// Turns a setting into a frame for a device, and the transition style
+    /// it asks for, if any.
+    fn resolve(&self, setting: &ColorSetting, device: &Device, status: &EngineStatus) -> Result<(Vec<Color>, Option<Transition>), ApplyError> {
+        let pixels = status.channels.iter().sum();
+        match setting {
+            ColorSetting::Profile(profile_name) => {
+                let profile = self.config.profile(profile_name)
+                    .ok_or_else(|| ApplyError::UnknownProfile(profile_name.clone()))?;
+                // Zones need per-pixel control; other devices get the base color
+                let frame = if status.capabilities.addressable {
+                    profile.render(&device.name, &status.channels, &self.config.layout).map_err(ApplyError::Failed)?
+                } else {
+                    vec![profile.base_color(); pixels]
+                };
+                Ok((frame, profile.transition))
+            }
+            ColorSetting::Custom(color) => {
+                Ok((vec![*color; pixels], None))
+            }
+            ColorSetting::Temperature(kelvin) => {
+                let color = Color::from_kelvin(*kelvin as f32).balance(device.config.white_point);
+                Ok((vec![color; pixels], None))
+            }
+            // Effects are started by `show_on` and never drawn as a single frame
+            ColorSetting::Effect { .. } => Ok((vec![Color::OFF; pixels], None)),
+            ColorSetting::Circadian => {
+                let position = self.circadian_position()
+                    .ok_or_else(|| ApplyError::Failed(anyhow::anyhow!("following the sun needs a [location]")))?;
+                let color = position.output().balance(device.config.white_point);
+                Ok((vec![color; pixels], None))
+            }
+        }
+    }
+
+    /// Draws the zones set with `SetZone` over a frame for `device`.
+    fn paint_zones(&self, name: &str, device: &EngineStatus, frame: &mut [Color]) -> Result<()> {
+        if !device.capabilities.addressable {
+            return Ok(());
+        }
+        for (zone, color) in &self.state.zones {
+            for index in self.config.layout.pixels(zone, name, &device.channels)? {
+                frame[index] = *color;
             }
         }
         Ok(())
     }
+
+    /// Shows `setting` on `device`, with the painted zones on top if `zones`.
+    /// Effects cover the zones. Without a setting the zones go over a dark frame. Whatever
+    /// `transition` leaves unset comes from the setting, then the config.
+    async fn show_on(&self, device: &Device, setting: Option<&ColorSetting>, transition: TransitionSpec, zones: bool) -> Result<(), ApplyError> {
+        let status = device.engine.status();
+        if let Some(ColorSetting::Effect { name, params }) = setting {
+            let kind = EffectKind::from_name(name)
+                .ok_or_else(|| ApplyError::Failed(anyhow::anyhow!("no effect named {:?}", name)))?;
+            let effect = effects::build(kind, params, &status.channels);
+            return device.engine.animate(effect).await.map_err(ApplyError::Failed);
+        }
+        let (mut frame, style) = match setting {
+            Some(setting) => self.resolve(setting, device, &status)?,
+            None => (vec![Color::OFF; status.channels.iter().sum()], None),
+        };
+        if zones {
+            self.paint_zones(&device.name, &status, &mut frame).map_err(ApplyError::Failed)?;
+        }
+        let transition = transition
+            .or(TransitionSpec { style, ..TransitionSpec::default() })
+            .or(self.config.transition);
+        device.engine.show(frame, transition).await.map_err(ApplyError::Failed)
+    }
+
+    /// The layers `device` draws, with their zones turned into pixels.
+    /// Layers covering zones the device doesn't have are left out.
+    fn overlays_for(&self, device: &Device, status: &EngineStatus) -> Vec<Overlay> {
+        self.layers.iter()
+            .filter(|active| active.devices.is_empty() || active.devices.contains(&device.name))
+            .filter_map(|active| {
+                let mask = if active.layer.zones.is_empty() {
+                    None
+                } else if !status.capabilities.addressable {
+                    return None;
+                } else {
+                    let mut pixels = Vec::new();
+                    for zone in &active.layer.zones {
+                        pixels.extend(self.config.layout.pixels(zone, &device.name, &status.channels).ok()?);
+                    }
+                    if pixels.is_empty() {
+                        return None;
+                    }
+                    Some(pixels)
+                };
+                Some(Overlay { layer: active.layer.clone(), mask, expires: active.expires })
+            })
+            .collect()
+    }
+
+    /// Sends every device its share of the layer stack.
+    async fn sync_layers(&self) -> Applied {
+        let mut applied = Applied::default();
+        for device in self.devices.devices() {
+            applied.devices.push(device.name.clone());
+            let overlays = self.overlays_for(device, &device.engine.status());
+            if let Err(e) = device.engine.set_layers(overlays).await {
+                applied.fail(&device.name, e);
+            }
+        }
+        applied
+    }
+
+    // The render threads drop expired layers themselves; this catches up
+    // with them and reports each one as popped
+    fn prune_layers(&mut self) {
+        let now = Instant::now();
+        let (expired, layers) = std::mem::take(&mut self.layers).into_iter()
+            .partition(|active| active.expires.is_some_and(|expires| expires <= now));
+        self.layers = layers;
+        for active in expired {
+            self.publish(Event::LayerPopped(active.layer.id));
+        }
+    }
+
+    // How long until the next layer expires, if any will
+    fn layer_wait(&self) -> Option<Duration> {
+        let now = Instant::now();
+        self.layers.iter()
+            .filter_map(|active| active.expires)
+            .min()
+            .map(|expires| expires.saturating_duration_since(now))
+    }
+
+    fn select(&self, targets: &[String]) -> Result<Vec<&Device>, Response> {
+        self.devices.select(targets)
+            .map_err(|name| Response::error(ErrorCode::UnknownDevice, format!("no device named {:?}", name)))
+    }
+
+    /// Shows `setting` on every targeted device, carrying on past devices
+    /// that fail. Only an unknown device or profile stops it up front.
+    /// Painted zones stay on top of a command aimed at some devices, and are
+    /// cleared by one aimed at all of them.
+    async fn apply(&self, targets: &[String], setting: &ColorSetting, transition: TransitionSpec) -> Result<Applied, Response> {
+        let mut applied = Applied::default();
+        let zones = !targets.is_empty();
+        for device in self.select(targets)? {
+            applied.devices.push(device.name.clone());
+            match self.show_on(device, Some(setting), transition, zones).await {
+                Ok(()) => {}
+                Err(ApplyError::UnknownProfile(name)) => {
+                    return Err(Response::error(ErrorCode::UnknownProfile, format!("no profile named {:?}", name)));
+                }
+                Err(ApplyError::Failed(e)) => applied.fail(&device.name, e),
+            }
+        }
+        Ok(applied)
+    }
+
+    /// Remembers what the devices in `applied` now show. A command sent to
+    /// every device replaces any per-device settings.
+    fn record(&mut self, targets: &[String], applied: &Applied, setting: ColorSetting, rgb: Option<RgbCommand>) {
+        if targets.is_empty() {
+            self.state.devices.clear();
+            self.state.zones.clear();
+            self.state.current_profile = if rgb.is_some() { None } else { Some(setting) };
+            self.state.last_color = rgb;
+        } else {
+            for device in &applied.devices {
+                self.state.devices.insert(device.clone(), setting.clone());
+            }
+        }
+        self.persist();
+    }
+
+    /// Dims or brightens every device without touching what they show.
+    async fn set_brightness(&mut self, brightness: f32) -> Applied {
+        let mut applied = Applied::default();
+        for device in self.devices.devices() {
+            applied.devices.push(device.name.clone());
+            if let Err(e) = device.engine.set_brightness(brightness).await {
+                applied.fail(&device.name, e);
+            }
+        }
+        if applied.any_succeeded() {
+            self.state.brightness = brightness;
+            self.persist();
+            self.publish(Event::BrightnessChanged(brightness));
+        }
+        applied
+    }
+
+    /// Saves the state so a restarted daemon comes back showing the same thing.
+    fn persist(&self) {
+        if let Err(e) = persist::save(&persist::state_path(), &self.state) {
+            eprintln!("Failed to save state: {}", e);
+        }
+    }
+
+    fn publish(&self, event: Event) {
+        // No subscribers is not an error
+        let _ = self.events.send(event);
+    }
+
+    fn status(&self) -> DaemonStatus {
+        let devices: Vec<DeviceStatus> = self.devices.devices().iter()
+            .map(|device| {
+                let engine = device.engine.status();
+                DeviceStatus {
+                    name: device.name.clone(),
+                    setting: self.state.setting_for(&device.name),
+                    connected: engine.connected,
+                    transitioning: engine.transitioning,
+                    capabilities: engine.capabilities,
+                }
+            })
+            .collect();
+        DaemonStatus {
+            setting: self.state.active_setting(),
+            last_color: self.state.last_color.clone(),
+            controller: devices.iter().map(|device| device.name.as_str()).collect::<Vec<_>>().join(", "),
+            connected: devices.iter().all(|device| device.connected),
+            transitioning: devices.iter().any(|device| device.transitioning),
+            devices,
+            zones: self.state.zones.clone(),
+            brightness: self.state.brightness,
+            layers: self.layers.iter()
+                .filter(|active| active.expires.is_none_or(|expires| expires > Instant::now()))
+                .map(|active| LayerStatus {
+                    layer: active.layer.clone(),
+                    devices: active.devices.clone(),
+                    expires_in_ms: active.expires.map(|expires| {
+                        expires.saturating_duration_since(Instant::now()).as_millis() as u64
+                    }),
+                })
+                .collect(),
+            circadian: self.devices.devices().iter()
+                .any(|device| self.state.setting_for(&device.name) == Some(ColorSetting::Circadian))
+                .then(|| self.circadian_position())
+                .flatten(),
+        }
+    }
+
+    /// Where on the circadian curve the sun puts us now, if we know where it is.
+    fn circadian_position(&self) -> Option<CurvePosition> {
+        let location = self.config.location.as_ref()?;
+        Some(self.config.circadian.position(location, chrono::Utc::now()))
+    }
+
+    /// Moves the devices following the sun along the curve.
+    async fn follow_sun(&self) {
+        let transition = TransitionSpec {
+            style: Some(Transition::Fade),
+            duration_ms: Some(CIRCADIAN_FADE.as_millis() as u64),
+            easing: Some(Easing::Linear),
+        };
+        for device in self.devices.devices() {
+            let setting = self.state.setting_for(&device.name);
+            if setting != Some(ColorSetting::Circadian) || !device.engine.status().connected {
+                continue;
+            }
+            if let Err(ApplyError::Failed(e)) = self.show_on(device, setting.as_ref(), transition, true).await {
+                eprintln!("[{}] Failed to follow the sun: {:#}", device.name, e);
+            }
+        }
+    }
+
+    /// Holds the schedule off after a manual change, for the configured period.
+    fn hold_schedule(&mut self) {
+        let period = self.config.schedule.override_period.0;
+        if period.is_zero() || self.config.schedule.rules.is_empty() {
+            return;
+        }
+        let until = chrono::Duration::from_std(period).ok()
+            .and_then(|period| Local::now().checked_add_signed(period));
+        match until {
+            Some(until) => self.schedule.override_until = Some(until),
+            // Too long to put a date on, so hold until resumed
+            None => {
+                self.schedule.paused = true;
+                self.schedule.paused_until = None;
+            }
+        }
+        self.schedule.wake.notify_one();
+    }
+
+    /// Moves the schedule up to `now`, returning the rules to run. Rules
+    /// that come due while paused are dropped; during an override the last
+    /// of them waits for it to end.
+    fn due_rules(&mut self, now: DateTime<Local>) -> Vec<usize> {
+        let schedule = &mut self.schedule;
+        if schedule.paused_until.is_some_and(|until| until <= now) {
+            schedule.paused = false;
+            schedule.paused_until = None;
+        }
+        let mut due = Vec::new();
+        if schedule.override_until.is_some_and(|until| until <= now) {
+            schedule.override_until = None;
+            due.extend(schedule.missed.take());
+        }
+        while let Some((index, time)) = self.config.schedule.next_after(schedule.checked, self.config.location.as_ref()).filter(|&(_, time)| time <= now) {
+            schedule.checked = time;
+            if schedule.paused {
+                println!("Schedule paused, skipping: {}", self.config.schedule.rules[index]);
+            } else if schedule.override_until.is_some() {
+                println!("Manual override, holding back: {}", self.config.schedule.rules[index]);
+                schedule.missed = Some(index);
+            } else {
+                due.push(index);
+            }
+        }
+        schedule.checked = now;
+        due
+    }
+
+    // How long the schedule task can sleep before something is due
+    fn schedule_wait(&self, now: DateTime<Local>) -> Duration {
+        let schedule = &self.schedule;
+        let next = self.config.schedule.next_after(schedule.checked, self.config.location.as_ref()).map(|(_, time)| time);
+        [next, schedule.paused_until, schedule.override_until].into_iter().flatten()
+            .map(|time| (time - now).to_std().unwrap_or_default())
+            .min()
+            // Check back now and then in case the clock jumps
+            .map_or(SCHEDULE_RECHECK, |wait| wait.min(SCHEDULE_RECHECK))
+    }
+
+    fn schedule_status(&self) -> ScheduleStatus {
+        let now = Local::now();
+        let location = self.config.location.as_ref();
+        ScheduleStatus {
+            rules: self.config.schedule.rules.iter()
+                .map(|rule| RuleStatus { rule: rule.clone(), next: rule.next_after(now, location) })
+                .collect(),
+            sunrise: location.and_then(|location| SolarEvent::Sunrise.time_on(now.date_naive(), location))
+                .map(|time| time.with_timezone(&Local)),
+            sunset: location.and_then(|location| SolarEvent::Sunset.time_on(now.date_naive(), location))
+                .map(|time| time.with_timezone(&Local)),
+            paused: self.schedule.paused,
+            paused_until: self.schedule.paused_until,
+            override_until: self.schedule.override_until,
+        }
+    }
+
+    /// Tells each device where its pixels sit, from the zones' `from` and `to`.
+    async fn place_pixels(&self, devices: &[&Device]) -> Applied {
+        let mut applied = Applied::default();
+        for device in devices {
+            applied.devices.push(device.name.clone());
+            let positions = self.config.layout.positions(&device.name, &device.engine.status().channels);
+            if let Err(e) = device.engine.set_positions(positions).await {
+                applied.fail(&device.name, e);
+            }
+        }
+        applied
+    }
+
+    /// Puts each device back to what it was last showing.
+    async fn restore_state(&self, devices: &[&Device]) -> Applied {
+        let mut applied = Applied::default();
+        for device in devices {
+            applied.devices.push(device.name.clone());
+            if let Err(e) = device.engine.set_brightness(self.state.brightness).await {
+                applied.fail(&device.name, e);
+                continue;
+            }
+            let setting = self.state.setting_for(&device.name);
+            if setting.is_none() && self.state.zones.is_empty() {
+                continue;
+            }
+            // Plain colors come back at once, profiles with their own transition
+            let transition = match setting {
+                Some(ColorSetting::Profile(_)) => TransitionSpec::default(),
+                _ => TransitionSpec::style(Transition::Instant),
+            };
+            match self.show_on(device, setting.as_ref(), transition, true).await {
+                Ok(()) => {}
+                Err(ApplyError::UnknownProfile(name)) => {
+                    applied.fail(&device.name, anyhow::anyhow!("profile {:?} no longer exists", name));
+                }
+                Err(ApplyError::Failed(e)) => applied.fail(&device.name, e),
+            }
+            // A new controller starts without layers
+            let overlays = self.overlays_for(device, &device.engine.status());
+            if !overlays.is_empty() {
+                if let Err(e) = device.engine.set_layers(overlays).await {
+                    applied.fail(&device.name, e);
+                }
+            }
+        }
+        applied
+    }
 }
 
-async fn run_daemon(socket_path: PathBuf) -> Result<()> {
+async fn run_daemon(socket_path: PathBuf, config: Config) -> Result<()> {
     // Remove existing socket file if it exists
     _ = std::fs::remove_file(&socket_path);
     
@@ -77,105 +784,733 @@ async fn run_daemon(socket_path: PathBuf) -> Result<()> {
     let listener = UnixListener::bind(&socket_path)?;
     println!("Daemon listening on {:?}", socket_path);
 
-    let state = Arc::new(Mutex::new(DaemonState::new()?));
+    let mut daemon = DaemonState::new(config)?;
+    let devices: Vec<&Device> = daemon.devices.devices().iter().collect();
+    if !daemon.place_pixels(&devices).await.failures.is_empty() {
+        eprintln!("Failed to place the pixels of every device");
+    }
+    match persist::load(&persist::state_path()) {
+        Ok(Some(saved)) => {
+            daemon.state = saved;
+            let devices: Vec<&Device> = daemon.devices.devices().iter().collect();
+            if !daemon.restore_state(&devices).await.failures.is_empty() {
+                eprintln!("Failed to restore saved state on every device");
+            }
+        }
+        Ok(None) => {}
+        Err(e) => eprintln!("Ignoring saved state: {}", e),
+    }
+    let state = Arc::new(Mutex::new(daemon));
+    tokio::spawn(run_schedule(state.clone()));
+    tokio::spawn(run_circadian(state.clone()));
+    tokio::spawn(run_layer_expiry(state.clone()));
     
     loop {
-        let (mut socket, _) = listener.accept().await?;
+        let (socket, _) = listener.accept().await?;
         let state = state.clone();
         
         tokio::spawn(async move {
-            let mut buffer = [0; 1024];
-            match socket.read(&mut buffer).await {
-                Ok(n) => {
-                    if let Ok(command) = serde_json::from_slice::<Command>(&buffer[..n]) {
-                        let mut state = state.lock().await;
-                        match command {
-                            Command::SetColor(rgb) => {
-                                println!("Daemon received SetColor command: RGB({}, {}, {})", 
-                                    rgb.red, rgb.green, rgb.blue);
-                                if let Err(e) = state.controller.set_color(
-                                    rgb.red,
-                                    rgb.green,
-                                    rgb.blue,
-                                ) {
-                                    eprintln!("Error setting color: {}", e);
-                                } else {
-                                    state.state.current_profile = None;
-                                    state.state.last_color = Some(rgb);
-                                }
-                            }
-                            Command::SetProfile(profile) => {
-                                println!("Daemon received SetProfile command: {:?}", profile);
-                                if let Some(mote) = state.controller.as_any().downcast_mut::<MoteController>() {
-                                    println!("Starting transition to profile...");
-                                    if let Err(e) = mote.transition_to(profile.clone()).await {
-                                        eprintln!("Error transitioning to profile: {}", e);
-                                    } else {
-                                        state.state.current_profile = Some(profile);
-                                        state.state.last_color = None;
-                                        println!("Transition complete");
-                                    }
-                                } else {
-                                    eprintln!("Controller doesn't support profiles");
-                                }
-                            }
-                            Command::Reconnect => {
-                                println!("Daemon received Reconnect command");
-                                // Create new controller instance
-                                match MoteController::new("Pimoroni Mote".to_string()) {
-                                    Ok(new_controller) => {
-                                        state.controller = Box::new(new_controller);
-                                        println!("Successfully reconnected to device");
-                                        
-                                        // Restore previous state
-                                        if let Err(e) = state.restore_state().await {
-                                            eprintln!("Failed to restore previous state: {}", e);
-                                        } else {
-                                            println!("Successfully restored previous state");
-                                        }
-                                    }
-                                    Err(e) => {
-                                        eprintln!("Failed to reconnect to device: {}", e);
-                                    }
-                                }
-                            }
-                        }
-                    } else {
-                        eprintln!("Failed to parse command from JSON");
-                        if let Ok(str_data) = std::str::from_utf8(&buffer[..n]) {
-                            eprintln!("Received data: {}", str_data);
-                        }
+            if let Err(e) = handle_connection(socket, state).await {
+                eprintln!("Connection error: {}", e);
+            }
+        });
+    }
+}
+
+/// Runs the rules of the schedule as they come due.
+async fn run_schedule(state: Arc<Mutex<DaemonState>>) {
+    let wake = state.lock().await.schedule.wake.clone();
+    loop {
+        let (due, wait) = {
+            let mut state = state.lock().await;
+            let due: Vec<_> = state.due_rules(Local::now()).into_iter()
+                .map(|index| state.config.schedule.rules[index].clone())
+                .collect();
+            (due, state.schedule_wait(Local::now()))
+        };
+        for rule in due {
+            println!("Schedule running: {}", rule);
+            for command in rule.commands() {
+                if let Response::Error { message, .. } = handle_command(&state, command).await {
+                    eprintln!("Scheduled rule failed: {}", message);
+                }
+            }
+        }
+        if wait.is_zero() {
+            continue;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = wake.notified() => {}
+        }
+    }
+}
+
+/// Keeps devices in circadian mode following the sun, a step every minute.
+async fn run_circadian(state: Arc<Mutex<DaemonState>>) {
+    let mut ticks = tokio::time::interval(CIRCADIAN_STEP);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        state.lock().await.follow_sun().await;
+    }
+}
+
+/// Reports layers as popped once their ttl runs out. The render threads
+/// have already stopped drawing them by then.
+async fn run_layer_expiry(state: Arc<Mutex<DaemonState>>) {
+    let changed = state.lock().await.layers_changed.clone();
+    loop {
+        let wait = {
+            let mut state = state.lock().await;
+            state.prune_layers();
+            state.layer_wait()
+        };
+        match wait {
+            Some(wait) => tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = changed.notified() => {}
+            },
+            None => changed.notified().await,
+        }
+    }
+}
+
+/// Whether `command` changes what the lights show, and so holds the schedule off.
+fn is_manual_change(command: &Command) -> bool {
+    match command {
+        Command::SetColor(_) | Command::SetProfile(_) | Command::SetZone { .. } | Command::SetEffect { .. } => true,
+        Command::Target { command, .. } | Command::Transition { command, .. } => is_manual_change(command),
+        _ => false,
+    }
+}
+
+async fn handle_connection(socket: UnixStream, state: Arc<Mutex<DaemonState>>) -> Result<()> {
+    let (reader, mut writer) = socket.into_split();
+    let mut reader = BufReader::new(reader);
+
+    // A connection can carry any number of commands; each gets one response.
+    loop {
+        let frame = match protocol::read_frame(&mut reader).await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => return Ok(()),
+            Err(e) if e.is::<FrameTooLarge>() => {
+                eprintln!("Rejecting oversized command: {}", e);
+                let response = Response::error(ErrorCode::FrameTooLarge, e.to_string());
+                protocol::write_frame(&mut writer, &response).await?;
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+
+        let response = match serde_json::from_slice::<Command>(&frame) {
+            Ok(Command::Subscribe) => {
+                let events = state.lock().await.events.subscribe();
+                protocol::write_frame(&mut writer, &Response::Ok).await?;
+                return stream_events(writer, events).await;
+            }
+            Ok(command) => {
+                let manual = is_manual_change(&command);
+                let response = handle_command(&state, command).await;
+                if manual && matches!(response, Response::Ok) {
+                    state.lock().await.hold_schedule();
+                }
+                response
+            }
+            Err(e) => {
+                eprintln!("Failed to parse command from JSON: {}", e);
+                if let Ok(str_data) = std::str::from_utf8(&frame) {
+                    eprintln!("Received data: {}", str_data);
+                }
+                Response::error(ErrorCode::InvalidRequest, format!("invalid command: {}", e))
+            }
+        };
+        protocol::write_frame(&mut writer, &response).await?;
+    }
+}
+
+async fn stream_events<W>(mut writer: W, mut events: broadcast::Receiver<Event>) -> Result<()>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    loop {
+        match events.recv().await {
+            Ok(event) => protocol::write_frame(&mut writer, &Response::Event(event)).await?,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                eprintln!("Subscriber fell behind, dropped {} events", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+/// Peels the `Target` and `Transition` wrappers off a command, in either order.
+fn unwrap_command(mut command: Command) -> (Vec<String>, TransitionSpec, Command) {
+    let mut targets = Vec::new();
+    let mut transition = TransitionSpec::default();
+    loop {
+        match command {
+            Command::Target { devices, command: inner } if targets.is_empty() => {
+                targets = devices;
+                command = *inner;
+            }
+            Command::Transition { transition: spec, command: inner } if transition.is_empty() => {
+                transition = spec;
+                command = *inner;
+            }
+            command => return (targets, transition, command),
+        }
+    }
+}
+
+async fn handle_command(state: &Mutex<DaemonState>, command: Command) -> Response {
+    let mut state = state.lock().await;
+    let (targets, transition, command) = unwrap_command(command);
+    match command {
+        Command::SetColor(rgb) => {
+            println!("Daemon received SetColor command: RGB({}, {}, {})", 
+                rgb.red, rgb.green, rgb.blue);
+            let setting = ColorSetting::Custom(Color { red: rgb.red, green: rgb.green, blue: rgb.blue });
+            // Colors change at once unless asked otherwise
+            let transition = transition.or(TransitionSpec::style(Transition::Instant));
+            let applied = match state.apply(&targets, &setting, transition).await {
+                Ok(applied) => applied,
+                Err(response) => return response,
+            };
+            if applied.any_succeeded() {
+                state.record(&targets, &applied, setting, Some(rgb.clone()));
+                state.publish(Event::ColorChanged(rgb));
+            }
+            applied.into_response()
+        }
+        Command::SetProfile(profile) => {
+            println!("Daemon received SetProfile command: {:?}", profile);
+            if let ColorSetting::Temperature(kelvin) = profile {
+                if !(MIN_KELVIN..=MAX_KELVIN).contains(&kelvin) {
+                    return Response::error(ErrorCode::InvalidRequest,
+                        format!("color temperatures go from {}K to {}K, not {}K", MIN_KELVIN, MAX_KELVIN, kelvin));
+                }
+            }
+            if profile == ColorSetting::Circadian && state.config.location.is_none() {
+                return Response::error(ErrorCode::InvalidRequest,
+                    "following the sun needs a [location] with your latitude and longitude");
+            }
+            // Brightness is global, so only a profile sent to every device brings its own
+            let brightness = match &profile {
+                ColorSetting::Profile(name) if targets.is_empty() => {
+                    state.config.profile(name).and_then(|profile| profile.brightness)
+                }
+                _ => None,
+            };
+            if let Some(brightness) = brightness {
+                let applied = state.set_brightness(brightness.clamp(0.0, 1.0)).await;
+                if !applied.any_succeeded() {
+                    return applied.into_response();
+                }
+            }
+            // The render threads carry on with the transition after we reply
+            let applied = match state.apply(&targets, &profile, transition).await {
+                Ok(applied) => applied,
+                Err(response) => return response,
+            };
+            if applied.any_succeeded() {
+                state.record(&targets, &applied, profile.clone(), None);
+                state.publish(Event::ProfileChanged(profile));
+            }
+            applied.into_response()
+        }
+        Command::SetZone { zone, color } => {
+            println!("Daemon received SetZone command: {} to RGB({}, {}, {})",
+                zone, color.red, color.green, color.blue);
+            let Some(zone) = state.config.layout.canonical_name(&zone) else {
+                return Response::error(ErrorCode::UnknownZone, format!("no zone named {:?}", zone));
+            };
+            let devices = match state.select(&targets) {
+                Ok(devices) => devices,
+                Err(response) => return response,
+            };
+            let names: Vec<String> = devices.into_iter()
+                .filter(|device| {
+                    let status = device.engine.status();
+                    status.capabilities.addressable && state.config.layout
+                        .pixels(&zone, &device.name, &status.channels)
+                        .is_ok_and(|pixels| !pixels.is_empty())
+                })
+                .map(|device| device.name.clone())
+                .collect();
+            if names.is_empty() {
+                return Response::error(ErrorCode::Unsupported, format!("zone {:?} isn't on any addressable device", zone));
+            }
+            // Effects draw every pixel themselves, so a painted zone would never show
+            if let Some(busy) = names.iter().find(|name| matches!(state.state.setting_for(name), Some(ColorSetting::Effect { .. }))) {
+                return Response::error(ErrorCode::Unsupported,
+                    format!("can't paint zone {:?} while an effect runs on {}; pick a color or profile first", zone, busy));
+            }
+
+            // Zones are drawn from the state, so record it first and undo that if nothing took it
+            let previous = state.state.zones.insert(zone.clone(), color);
+            let mut applied = Applied::default();
+            for device in state.devices.select(&names).unwrap_or_default() {
+                applied.devices.push(device.name.clone());
+                let setting = state.state.setting_for(&device.name);
+                match state.show_on(device, setting.as_ref(), transition, true).await {
+                    Ok(()) => {}
+                    Err(ApplyError::UnknownProfile(name)) => {
+                        applied.fail(&device.name, anyhow::anyhow!("profile {:?} no longer exists", name));
                     }
+                    Err(ApplyError::Failed(e)) => applied.fail(&device.name, e),
                 }
-                Err(e) => eprintln!("Error reading from socket: {}", e),
             }
-        });
+            if applied.any_succeeded() {
+                state.persist();
+                state.publish(Event::ZoneChanged { zone, color });
+            } else {
+                match previous {
+                    Some(color) => state.state.zones.insert(zone, color),
+                    None => state.state.zones.remove(&zone),
+                };
+            }
+            applied.into_response()
+        }
+        Command::SetEffect { name, params } => {
+            println!("Daemon received SetEffect command: {} {:?}", name, params);
+            let Some(kind) = EffectKind::from_name(&name) else {
+                let known: Vec<&str> = EffectKind::ALL.iter().map(|kind| kind.name()).collect();
+                return Response::error(ErrorCode::UnknownEffect,
+                    format!("no effect named {:?}; try {}", name, known.join(", ")));
+            };
+            let setting = ColorSetting::Effect { name: kind.name().to_string(), params };
+            let applied = match state.apply(&targets, &setting, transition).await {
+                Ok(applied) => applied,
+                Err(response) => return response,
+            };
+            if applied.any_succeeded() {
+                state.record(&targets, &applied, setting.clone(), None);
+                state.publish(Event::ProfileChanged(setting));
+            }
+            applied.into_response()
+        }
+        Command::SetBrightness(brightness) => {
+            println!("Daemon received SetBrightness command: {:.0}%", brightness * 100.0);
+            if !targets.is_empty() {
+                return Response::error(ErrorCode::InvalidRequest, "brightness applies to every device");
+            }
+            if !(0.0..=1.0).contains(&brightness) {
+                return Response::error(ErrorCode::InvalidRequest,
+                    format!("brightness goes from 0 to 1, not {}", brightness));
+            }
+            state.set_brightness(brightness).await.into_response()
+        }
+        Command::PushLayer(mut layer) => {
+            println!("Daemon received PushLayer command: {:?}", layer);
+            if !(0.0..=1.0).contains(&layer.opacity) {
+                return Response::error(ErrorCode::InvalidRequest,
+                    format!("opacity goes from 0 to 1, not {}", layer.opacity));
+            }
+            for zone in &mut layer.zones {
+                match state.config.layout.canonical_name(zone) {
+                    Some(name) => *zone = name,
+                    None => return Response::error(ErrorCode::UnknownZone, format!("no zone named {:?}", zone)),
+                }
+            }
+            let devices = match state.select(&targets) {
+                Ok(devices) => devices,
+                Err(response) => return response,
+            };
+            let devices = if targets.is_empty() {
+                Vec::new()
+            } else {
+                devices.into_iter().map(|device| device.name.clone()).collect()
+            };
+
+            state.prune_layers();
+            let id = layer.id.clone();
+            let previous = state.layers.iter().position(|active| active.layer.id == id)
+                .map(|index| state.layers.remove(index));
+            let expires = layer.ttl().map(|ttl| Instant::now() + ttl);
+            state.layers.push(ActiveLayer { layer, devices, expires });
+            let applied = state.sync_layers().await;
+            if applied.any_succeeded() {
+                state.publish(Event::LayerPushed(id));
+                if expires.is_some() {
+                    state.layers_changed.notify_one();
+                }
+            } else {
+                state.layers.pop();
+                state.layers.extend(previous);
+            }
+            applied.into_response()
+        }
+        Command::PopLayer { id } => {
+            println!("Daemon received PopLayer command: {}", id);
+            if !targets.is_empty() {
+                return Response::error(ErrorCode::InvalidRequest, "layers are popped from every device they were pushed to");
+            }
+            state.prune_layers();
+            let Some(index) = state.layers.iter().position(|active| active.layer.id == id) else {
+                return Response::error(ErrorCode::UnknownLayer, format!("no layer named {:?}", id));
+            };
+            state.layers.remove(index);
+            let applied = state.sync_layers().await;
+            state.publish(Event::LayerPopped(id));
+            applied.into_response()
+        }
+        Command::GetSchedule => Response::Schedule(state.schedule_status()),
+        Command::PauseSchedule { duration_ms } => {
+            println!("Daemon received PauseSchedule command: {:?}", duration_ms);
+            let until = duration_ms.map(|ms| chrono::Duration::from_std(Duration::from_millis(ms)).ok()
+                .and_then(|duration| Local::now().checked_add_signed(duration))
+                .ok_or(ms));
+            let until = match until.transpose() {
+                Ok(until) => until,
+                Err(ms) => return Response::error(ErrorCode::InvalidRequest,
+                    format!("cannot pause for {}ms; leave out the duration to pause until resumed", ms)),
+            };
+            state.schedule.paused = true;
+            state.schedule.paused_until = until;
+            state.schedule.wake.notify_one();
+            Response::Ok
+        }
+        Command::ResumeSchedule => {
+            println!("Daemon received ResumeSchedule command");
+            let schedule = &mut state.schedule;
+            schedule.paused = false;
+            schedule.paused_until = None;
+            schedule.override_until = None;
+            schedule.missed = None;
+            schedule.wake.notify_one();
+            Response::Ok
+        }
+        Command::Reconnect => {
+            println!("Daemon received Reconnect command");
+            let devices = match state.select(&targets) {
+                Ok(devices) => devices,
+                Err(response) => return response,
+            };
+            // Create new controller instances, then restore what they showed
+            let mut applied = Applied::default();
+            let mut reconnected = Vec::new();
+            for device in devices {
+                match device.reconnect().await {
+                    Ok(()) => {
+                        println!("Successfully reconnected to {}", device.name);
+                        reconnected.push(device);
+                    }
+                    Err(e) => {
+                        applied.devices.push(device.name.clone());
+                        applied.fail(&device.name, e.context("failed to reconnect"));
+                    }
+                }
+            }
+            let placed = state.place_pixels(&reconnected).await;
+            applied.failures.extend(placed.failures);
+            let restored = state.restore_state(&reconnected).await;
+            applied.devices.extend(restored.devices);
+            applied.failures.extend(restored.failures);
+            applied.into_response()
+        }
+        Command::GetState => Response::State(Box::new(state.status())),
+        // Subscriptions take over the connection and are handled before we get here
+        Command::Subscribe => Response::error(ErrorCode::InvalidRequest, "unexpected Subscribe"),
+        Command::Target { .. } => Response::error(ErrorCode::InvalidRequest, "targets can't be nested"),
+        Command::Transition { .. } => Response::error(ErrorCode::InvalidRequest, "transitions can't be nested"),
     }
 }
 
-async fn send_command(socket_path: PathBuf, command: Command) -> Result<()> {
-    let mut stream = tokio::net::UnixStream::connect(socket_path).await?;
-    let command_json = serde_json::to_vec(&command)?;
-    stream.write_all(&command_json).await?;
+async fn watch_events(socket_path: PathBuf, json: bool) -> Result<()> {
+    let mut events = RgbClient::connect(socket_path).await?.subscribe().await?;
+    while let Some(event) = events.next_event().await? {
+        if json {
+            println!("{}", serde_json::to_string(&event)?);
+        } else {
+            print_event(&event);
+        }
+    }
     Ok(())
 }
 
+fn print_event(event: &Event) {
+    match event {
+        Event::ColorChanged(rgb) => println!("color      RGB({}, {}, {})", rgb.red, rgb.green, rgb.blue),
+        Event::ProfileChanged(ColorSetting::Profile(name)) => println!("profile    {}", name),
+        Event::ProfileChanged(ColorSetting::Custom(color)) => {
+            println!("profile    RGB({}, {}, {})", color.red, color.green, color.blue)
+        }
+        Event::ProfileChanged(ColorSetting::Temperature(kelvin)) => println!("profile    {}K", kelvin),
+        Event::ProfileChanged(ColorSetting::Effect { name, .. }) => println!("effect     {}", name),
+        Event::ProfileChanged(ColorSetting::Circadian) => println!("profile    circadian"),
+        Event::ZoneChanged { zone, color } => {
+            println!("zone       {} RGB({}, {}, {})", zone, color.red, color.green, color.blue)
+        }
+        Event::ConnectionChanged { device, connected: true } => println!("controller {} connected", device),
+        Event::ConnectionChanged { device, connected: false } => println!("controller {} disconnected", device),
+        Event::TransitionProgress(progress) => println!("transition {:.0}%", progress * 100.0),
+        Event::BrightnessChanged(brightness) => println!("brightness {:.0}%", brightness * 100.0),
+        Event::LayerPushed(id) => println!("layer      {} pushed", id),
+        Event::LayerPopped(id) => println!("layer      {} popped", id),
+    }
+}
+
+fn print_status(status: &DaemonStatus) {
+    let health = if status.connected { "connected" } else { "disconnected" };
+    println!("Controller: {} ({})", status.controller, health);
+    match &status.setting {
+        Some(ColorSetting::Profile(profile)) => println!("Profile:    {}", profile),
+        Some(ColorSetting::Custom(color)) => {
+            println!("Color:      RGB({}, {}, {})", color.red, color.green, color.blue)
+        }
+        Some(ColorSetting::Temperature(kelvin)) => println!("White:      {}K", kelvin),
+        Some(ColorSetting::Effect { name, .. }) => println!("Effect:     {}", name),
+        Some(ColorSetting::Circadian) => println!("Profile:    circadian"),
+        None => println!("Profile:    none"),
+    }
+    for (zone, color) in &status.zones {
+        println!("Zone:       {} RGB({}, {}, {})", zone, color.red, color.green, color.blue);
+    }
+    println!("Brightness: {:.0}%", status.brightness * 100.0);
+    if let Some(position) = &status.circadian {
+        print_curve_position(position);
+    }
+    for layer in status.layers.iter().rev() {
+        print_layer(layer);
+    }
+    println!("Transition: {}", if status.transitioning { "running" } else { "idle" });
+
+    if status.devices.len() > 1 {
+        println!("Devices:");
+        for device in &status.devices {
+            let health = if device.connected { "connected" } else { "disconnected" };
+            let setting = match &device.setting {
+                Some(ColorSetting::Profile(profile)) => profile.clone(),
+                Some(ColorSetting::Custom(color)) => format!("RGB({}, {}, {})", color.red, color.green, color.blue),
+                Some(ColorSetting::Temperature(kelvin)) => format!("{}K", kelvin),
+                Some(ColorSetting::Effect { name, .. }) => format!("effect {}", name),
+                Some(ColorSetting::Circadian) => "circadian".to_string(),
+                None => "none".to_string(),
+            };
+            println!("  {:<16} {:<12} {}", device.name, health, setting);
+        }
+    }
+}
+
+fn print_curve_position(position: &CurvePosition) {
+    let shade = match position.kelvin {
+        Some(kelvin) => format!("{}K", kelvin),
+        None => format!("RGB({}, {}, {})", position.color.red, position.color.green, position.color.blue),
+    };
+    println!("Circadian:  sun at {:.1}°, {} at {:.0}%",
+        position.elevation, shade, position.brightness * 100.0);
+}
+
+fn print_layer(status: &LayerStatus) {
+    let layer = &status.layer;
+    let mut line = format!("Layer:      {} RGB({}, {}, {}) {:?}",
+        layer.id, layer.color.red, layer.color.green, layer.color.blue, layer.blend);
+    if matches!(layer.blend, BlendMode::Add | BlendMode::Alpha) {
+        line.push_str(&format!(" {:.0}%", layer.opacity * 100.0));
+    }
+    line.push_str(&format!(", priority {}", layer.priority));
+    if !layer.zones.is_empty() {
+        line.push_str(&format!(", zones {}", layer.zones.join(", ")));
+    }
+    if !status.devices.is_empty() {
+        line.push_str(&format!(", on {}", status.devices.join(", ")));
+    }
+    if let Some(ms) = status.expires_in_ms {
+        line.push_str(&format!(", {:.1}s left", ms as f32 / 1000.0));
+    }
+    println!("{}", line);
+}
+
+fn print_rule(status: &RuleStatus) {
+    let next = match status.next {
+        Some(next) => next.format("%a %d %b %H:%M").to_string(),
+        None => "never".to_string(),
+    };
+    println!("{:<16} {}", next, status.rule);
+}
+
+fn print_schedule_state(status: &ScheduleStatus) {
+    let time = |time: Option<DateTime<Local>>| time.map_or("none".to_string(), |time| time.format("%H:%M").to_string());
+    if status.sunrise.is_some() || status.sunset.is_some() {
+        println!("Today the sun rises at {} and sets at {}", time(status.sunrise), time(status.sunset));
+    }
+    match (status.paused, status.paused_until) {
+        (true, Some(until)) => println!("Paused until {}", until.format("%a %d %b %H:%M")),
+        (true, None) => println!("Paused until resumed"),
+        _ => {}
+    }
+    if let Some(until) = status.override_until {
+        println!("Held off by a manual change until {}", until.format("%a %d %b %H:%M"));
+    }
+}
+
+fn print_devices(devices: &[DeviceStatus]) {
+    for device in devices {
+        let caps = &device.capabilities;
+        let health = if device.connected { "connected" } else { "disconnected" };
+        println!("{} ({})", device.name, health);
+        if caps.addressable {
+            println!("  pixels:      {} in {} zones", caps.pixels, caps.zones);
+        } else {
+            println!("  pixels:      single color");
+        }
+        let mut features = Vec::new();
+        if caps.hardware_brightness {
+            features.push("hardware brightness");
+        }
+        if caps.native_transitions {
+            features.push("native transitions");
+        }
+        if caps.rgbw {
+            features.push("RGBW");
+        }
+        if !features.is_empty() {
+            println!("  features:    {}", features.join(", "));
+        }
+        if let Some(rate) = caps.max_refresh_rate {
+            println!("  refresh:     up to {} fps", rate);
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Daemon { socket } => {
+        Commands::Daemon { socket, config, controller } => {
             println!("Starting daemon...");
-            run_daemon(socket).await?;
+            let mut config = match config {
+                Some(path) => Config::load_from(&path)?,
+                None => Config::load()?,
+            };
+            if let Some(backend) = controller {
+                // Keep the settings of a configured controller of that kind, if any
+                let controller = config.controllers.into_iter()
+                    .find(|controller| controller.backend == backend)
+                    .unwrap_or_else(|| ControllerConfig { backend, ..Default::default() });
+                config.controllers = vec![controller];
+            }
+            run_daemon(socket, config).await?;
         }
-        Commands::Set { red, green, blue, socket } => {
+        Commands::Set { color, transition, devices, socket } => {
+            let Color { red, green, blue } = color.color();
             println!("Setting color to RGB({}, {}, {})", red, green, blue);
-            send_command(socket, Command::SetColor(RgbCommand { red, green, blue })).await?;
+            let command = Command::SetColor(RgbCommand { red, green, blue })
+                .with_transition(transition.spec())
+                .targeted(devices);
+            RgbClient::connect(socket).await?.request(command).await?;
+        }
+        Commands::Profile { profile, transition, devices, socket } => {
+            println!("Activating profile: {}", profile);
+            let command = Command::SetProfile(ColorSetting::Profile(profile))
+                .with_transition(transition.spec())
+                .targeted(devices);
+            RgbClient::connect(socket).await?.request(command).await?;
         }
-        Commands::Profile { profile, socket } => {
-            println!("Activating profile: {:?}", profile);
-            send_command(socket, Command::SetProfile(ColorSetting::from(profile))).await?;
+        Commands::Temp { kelvin, transition, devices, socket } => {
+            println!("Switching to white at {}K", kelvin);
+            let command = Command::SetProfile(ColorSetting::Temperature(kelvin))
+                .with_transition(transition.spec())
+                .targeted(devices);
+            RgbClient::connect(socket).await?.request(command).await?;
+        }
+        Commands::Effect { name, speed, color, background, devices, socket } => {
+            println!("Starting effect: {}", name.name());
+            let params = EffectParams { speed, color, background: background.unwrap_or(Color::OFF) };
+            let command = Command::SetEffect { name: name.name().to_string(), params }.targeted(devices);
+            RgbClient::connect(socket).await?.request(command).await?;
+        }
+        Commands::Circadian { transition, devices, socket } => {
+            println!("Following the sun");
+            let command = Command::SetProfile(ColorSetting::Circadian)
+                .with_transition(transition.spec())
+                .targeted(devices);
+            RgbClient::connect(socket).await?.request(command).await?;
+        }
+        Commands::Brightness { level, socket } => {
+            let mut client = RgbClient::connect(socket).await?;
+            let brightness = match level {
+                BrightnessLevel::Absolute(brightness) => brightness,
+                BrightnessLevel::Relative(change) => (client.state().await?.brightness + change).clamp(0.0, 1.0),
+            };
+            println!("Setting brightness to {:.0}%", brightness * 100.0);
+            client.set_brightness(brightness).await?;
+        }
+        Commands::Zone { zone, color, transition, devices, socket } => {
+            let color = color.color();
+            println!("Setting zone {} to RGB({}, {}, {})", zone, color.red, color.green, color.blue);
+            let command = Command::SetZone { zone, color }
+                .with_transition(transition.spec())
+                .targeted(devices);
+            RgbClient::connect(socket).await?.request(command).await?;
+        }
+        Commands::Layer { action: LayerAction::Push { id, color, priority, blend, opacity, ttl, zones, devices, socket } } => {
+            println!("Pushing layer {}", id);
+            let layer = Layer {
+                id,
+                color: color.color(),
+                priority,
+                blend,
+                opacity,
+                ttl_ms: ttl.map(|secs| Duration::from_secs_f32(secs.max(0.0)).as_millis() as u64),
+                zones,
+            };
+            let command = Command::PushLayer(layer).targeted(devices);
+            RgbClient::connect(socket).await?.request(command).await?;
+        }
+        Commands::Layer { action: LayerAction::Pop { id, socket } } => {
+            println!("Popping layer {}", id);
+            RgbClient::connect(socket).await?.pop_layer(id).await?;
+        }
+        Commands::Schedule { action: ScheduleAction::List { socket } } => {
+            let status = RgbClient::connect(socket).await?.schedule().await?;
+            if status.rules.is_empty() {
+                println!("No schedule rules configured");
+            }
+            for rule in &status.rules {
+                print_rule(rule);
+            }
+            print_schedule_state(&status);
+        }
+        Commands::Schedule { action: ScheduleAction::Next { socket } } => {
+            let status = RgbClient::connect(socket).await?.schedule().await?;
+            match status.next() {
+                Some(rule) => print_rule(rule),
+                None => println!("No rule is due to fire"),
+            }
+            print_schedule_state(&status);
+        }
+        Commands::Schedule { action: ScheduleAction::Pause { duration, socket } } => {
+            match duration {
+                Some(duration) => println!("Pausing the schedule for {}", duration),
+                None => println!("Pausing the schedule"),
+            }
+            RgbClient::connect(socket).await?.pause_schedule(duration.map(|span| span.0)).await?;
+        }
+        Commands::Schedule { action: ScheduleAction::Resume { socket } } => {
+            println!("Resuming the schedule");
+            RgbClient::connect(socket).await?.resume_schedule().await?;
+        }
+        Commands::Watch { json, socket } => {
+            watch_events(socket, json).await?;
+        }
+        Commands::Devices { json, socket } => {
+            let devices = RgbClient::connect(socket).await?.state().await?.devices;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&devices)?);
+            } else {
+                print_devices(&devices);
+            }
+        }
+        Commands::Status { json, socket } => {
+            let status = RgbClient::connect(socket).await?.state().await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&status)?);
+            } else {
+                print_status(&status);
+            }
         }
     }
 
@@ -0,0 +1,135 @@
+use crate::config::xdg_dir;
+use crate::MoteState;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Bump this when the layout of `MoteState` changes in a way that `#[serde(default)]`
+/// can't absorb, and add a step to `migrate`.
+pub const STATE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct StateFile {
+    version: u32,
+    #[serde(flatten)]
+    state: MoteState,
+}
+
+/// `$XDG_STATE_HOME/rgbd/state.json`, falling back to `~/.local/state`.
+pub fn state_path() -> PathBuf {
+    xdg_dir("XDG_STATE_HOME", ".local/state").join("rgbd").join("state.json")
+}
+
+/// Reads the saved state, or `None` if nothing has been saved yet.
+pub fn load(path: &Path) -> Result<Option<MoteState>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+
+    let value: Value = serde_json::from_str(&contents)
+        .with_context(|| format!("Corrupt state file {}", path.display()))?;
+    let version = value.get("version").and_then(Value::as_u64).unwrap_or(0) as u32;
+    let file: StateFile = serde_json::from_value(migrate(value, version)?)?;
+    Ok(Some(file.state))
+}
+
+fn migrate(value: Value, version: u32) -> Result<Value> {
+    match version {
+        STATE_VERSION => Ok(value),
+        v if v > STATE_VERSION => bail!("state file version {} is newer than this rgbd ({})", v, STATE_VERSION),
+        v => bail!("unknown state file version {}", v),
+    }
+}
+
+/// Writes the state next to its final location and renames it into place,
+/// so a crash mid-write never leaves a truncated file behind.
+pub fn save(path: &Path, state: &MoteState) -> Result<()> {
+    let dir = path.parent().context("state path has no parent directory")?;
+    std::fs::create_dir_all(dir)?;
+
+    let file = StateFile {
+        version: STATE_VERSION,
+        state: state.clone(),
+    };
+    let tmp_path = path.with_extension("json.tmp");
+    let mut tmp = std::fs::File::create(&tmp_path)?;
+    tmp.write_all(&serde_json::to_vec_pretty(&file)?)?;
+    tmp.sync_all()?;
+    std::fs::rename(&tmp_path, path)
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rgb_controller::profiles::{Color, ColorSetting};
+
+    // A directory of its own under the system temp dir, removed afterwards
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("rgbd-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn json(state: &MoteState) -> Value {
+        serde_json::to_value(state).unwrap()
+    }
+
+    #[test]
+    fn saves_and_loads() {
+        let dir = TempDir::new("round-trip");
+        let path = dir.0.join("rgbd").join("state.json");
+        let mut state = MoteState {
+            current_profile: Some(ColorSetting::Temperature(2700)),
+            brightness: 0.4,
+            ..Default::default()
+        };
+        state.devices.insert("Desk".to_string(), ColorSetting::Profile("Night".to_string()));
+        state.zones.insert("left".to_string(), Color { red: 255, green: 0, blue: 0 });
+
+        save(&path, &state).unwrap();
+        assert_eq!(json(&load(&path).unwrap().unwrap()), json(&state));
+        // The temporary file was renamed into place
+        assert!(!path.with_extension("json.tmp").exists());
+
+        state.zones.clear();
+        save(&path, &state).unwrap();
+        assert_eq!(json(&load(&path).unwrap().unwrap()), json(&state));
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let dir = TempDir::new("newer");
+        std::fs::create_dir_all(&dir.0).unwrap();
+        let path = dir.0.join("state.json");
+        std::fs::write(&path, format!("{{\"version\": {}}}", STATE_VERSION + 1)).unwrap();
+        let error = load(&path).unwrap_err();
+        assert!(error.to_string().contains("newer"), "{}", error);
+    }
+
+    #[test]
+    fn missing_file_loads_nothing() {
+        let dir = TempDir::new("missing");
+        assert!(load(&dir.0.join("state.json")).unwrap().is_none());
+        // and an empty file at the current version is the default state
+        std::fs::create_dir_all(&dir.0).unwrap();
+        let path = dir.0.join("state.json");
+        std::fs::write(&path, format!("{{\"version\": {}}}", STATE_VERSION)).unwrap();
+        assert_eq!(json(&load(&path).unwrap().unwrap()), json(&MoteState::default()));
+    }
+}
@@ -0,0 +1,152 @@
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+// Messages are newline-delimited JSON. serde_json escapes newlines inside
+// strings, so a single '\n' always marks the end of a frame.
+pub const MAX_FRAME_LEN: usize = 64 * 1024;
+
+#[derive(Debug)]
+pub struct FrameTooLarge;
+
+impl fmt::Display for FrameTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "frame exceeds {} bytes", MAX_FRAME_LEN)
+    }
+}
+
+impl std::error::Error for FrameTooLarge {}
+
+/// Reads one frame without its trailing newline. Returns `None` when the
+/// peer closed the connection cleanly between frames.
+pub async fn read_frame<R>(reader: &mut R) -> Result<Option<Vec<u8>>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut frame = Vec::new();
+    loop {
+        let available = reader.fill_buf().await?;
+        if available.is_empty() {
+            if frame.is_empty() {
+                return Ok(None);
+            }
+            anyhow::bail!("connection closed in the middle of a frame");
+        }
+
+        let (chunk, done) = match available.iter().position(|&b| b == b'\n') {
+            Some(end) => (&available[..end], Some(end + 1)),
+            None => (available, None),
+        };
+        if frame.len() + chunk.len() > MAX_FRAME_LEN {
+            return Err(FrameTooLarge.into());
+        }
+        frame.extend_from_slice(chunk);
+
+        match done {
+            Some(consumed) => {
+                reader.consume(consumed);
+                return Ok(Some(frame));
+            }
+            None => {
+                let consumed = available.len();
+                reader.consume(consumed);
+            }
+        }
+    }
+}
+
+pub async fn write_frame<W, T>(writer: &mut W, message: &T) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let mut frame = serde_json::to_vec(message)?;
+    frame.push(b'\n');
+    writer.write_all(&frame).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Reads and decodes one message, or `None` on a clean end of stream.
+pub async fn read_message<R, T>(reader: &mut R) -> Result<Option<T>>
+where
+    R: AsyncBufRead + Unpin,
+    T: DeserializeOwned,
+{
+    match read_frame(reader).await? {
+        Some(frame) => Ok(Some(serde_json::from_slice(&frame)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::{AsyncRead, BufReader, ReadBuf};
+
+    // Hands out its chunks one read at a time, like a socket would
+    struct Chunks(VecDeque<Vec<u8>>);
+
+    impl AsyncRead for Chunks {
+        fn poll_read(mut self: Pin<&mut Self>, _: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+            if let Some(mut chunk) = self.0.pop_front() {
+                let len = chunk.len().min(buf.remaining());
+                buf.put_slice(&chunk[..len]);
+                if len < chunk.len() {
+                    self.0.push_front(chunk.split_off(len));
+                }
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn reader(chunks: &[&[u8]]) -> BufReader<Chunks> {
+        BufReader::new(Chunks(chunks.iter().map(|chunk| chunk.to_vec()).collect()))
+    }
+
+    #[tokio::test]
+    async fn joins_frames_split_across_reads() {
+        let mut reader = reader(&[b"{\"Set", b"Pro", b"file\":\"Night\"}\n"]);
+        assert_eq!(read_frame(&mut reader).await.unwrap().unwrap(), b"{\"SetProfile\":\"Night\"}");
+        assert!(read_frame(&mut reader).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn splits_several_frames_in_one_read() {
+        let mut reader = reader(&[b"one\ntwo\n\nthree\n"]);
+        for frame in [&b"one"[..], b"two", b"", b"three"] {
+            assert_eq!(read_frame(&mut reader).await.unwrap().unwrap(), frame);
+        }
+        assert!(read_frame(&mut reader).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_frames_over_the_limit() {
+        let mut frame = vec![b'x'; MAX_FRAME_LEN];
+        frame.push(b'\n');
+        assert_eq!(read_frame(&mut reader(&[&frame])).await.unwrap().unwrap().len(), MAX_FRAME_LEN);
+
+        let mut frame = vec![b'x'; MAX_FRAME_LEN + 1];
+        frame.push(b'\n');
+        let error = read_frame(&mut reader(&[&frame])).await.unwrap_err();
+        assert!(error.is::<FrameTooLarge>(), "{}", error);
+        // Also when no single read is over it
+        let (first, second) = frame.split_at(MAX_FRAME_LEN / 2);
+        let error = read_frame(&mut reader(&[first, second])).await.unwrap_err();
+        assert!(error.is::<FrameTooLarge>(), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn fails_on_eof_mid_frame() {
+        let mut reader = reader(&[b"done\n", b"{\"Set"]);
+        assert_eq!(read_frame(&mut reader).await.unwrap().unwrap(), b"done");
+        let error = read_frame(&mut reader).await.unwrap_err();
+        assert!(!error.is::<FrameTooLarge>());
+        assert!(error.to_string().contains("middle of a frame"), "{}", error);
+    }
+}
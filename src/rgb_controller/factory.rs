@@ -0,0 +1,168 @@
+use super::gpio::GPIOController;
+use super::mock::MockController;
+use super::mote::{MoteChannel, MAX_REFRESH_RATE};
+use super::openrgb::{protocol::DEFAULT_PORT, OpenRGBController};
+use super::profiles::Color;
+use super::RgbController;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Mock,
+    #[default]
+    Mote,
+    Gpio,
+    Openrgb,
+}
+
+/// The `[controller]` table of the config file: which backend the daemon
+/// drives and how to reach it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ControllerConfig {
+    pub backend: Backend,
+    /// Display name, defaults to one per backend.
+    pub name: Option<String>,
+    pub mock: MockOptions,
+    pub mote: MoteOptions,
+    pub gpio: GpioOptions,
+    pub openrgb: OpenRgbOptions,
+    /// What to send for a neutral white, e.g. less blue for strips that run
+    /// cold. Color temperatures are scaled by it.
+    pub white_point: Color,
+}
+
+impl Default for ControllerConfig {
+    fn default() -> Self {
+        Self {
+            backend: Backend::default(),
+            name: None,
+            mock: MockOptions::default(),
+            mote: MoteOptions::default(),
+            gpio: GpioOptions::default(),
+            openrgb: OpenRgbOptions::default(),
+            white_point: Color::WHITE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MockOptions {
+    /// Pixels per channel; a single pixel unless set.
+    pub channels: Vec<usize>,
+}
+
+impl Default for MockOptions {
+    fn default() -> Self {
+        Self { channels: vec![1] }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MoteOptions {
+    /// Serial device of the dock, found by USB id when unset.
+    pub port: Option<String>,
+    /// One entry per port in use, in port order.
+    pub channels: Vec<MoteChannel>,
+    /// Most frames a second sent to the dock.
+    pub fps: u32,
+}
+
+impl Default for MoteOptions {
+    fn default() -> Self {
+        Self {
+            port: None,
+            channels: MoteChannel::defaults(),
+            fps: MAX_REFRESH_RATE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GpioOptions {
+    pub red_pin: u8,
+    pub green_pin: u8,
+    pub blue_pin: u8,
+}
+
+impl Default for GpioOptions {
+    fn default() -> Self {
+        Self {
+            red_pin: 17,
+            green_pin: 27,
+            blue_pin: 22,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OpenRgbOptions {
+    pub address: String,
+    pub port: u16,
+}
+
+impl Default for OpenRgbOptions {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1".to_string(),
+            port: DEFAULT_PORT,
+        }
+    }
+}
+
+impl ControllerConfig {
+    /// Opens the configured backend. Called again on every reconnect.
+    pub fn build(&self) -> Result<Box<dyn RgbController>> {
+        let name = self.device_name();
+        Ok(match self.backend {
+            Backend::Mock => Box::new(MockController::with_layout(name, self.mock.channels.clone())),
+            Backend::Mote => self.build_mote(name)?,
+            Backend::Gpio => {
+                let gpio = &self.gpio;
+                Box::new(GPIOController::new(name, gpio.red_pin, gpio.green_pin, gpio.blue_pin))
+            }
+            Backend::Openrgb => {
+                let openrgb = &self.openrgb;
+                Box::new(OpenRGBController::new(name, openrgb.address.clone(), openrgb.port)?)
+            }
+        })
+    }
+
+    /// The name commands use to address this device.
+    pub fn device_name(&self) -> String {
+        let default = match self.backend {
+            Backend::Mock => "Mock",
+            Backend::Mote => "Pimoroni Mote",
+            Backend::Gpio => "GPIO",
+            Backend::Openrgb => "OpenRGB",
+        };
+        self.name.clone().unwrap_or_else(|| default.to_string())
+    }
+
+    #[cfg(not(feature = "python"))]
+    fn build_mote(&self, name: String) -> Result<Box<dyn RgbController>> {
+        use super::mote::MoteController;
+        let channels = self.mote.channels.clone();
+        let mote = match &self.mote.port {
+            Some(path) => MoteController::open(name, path, channels)?,
+            None => MoteController::new(name, channels)?,
+        };
+        Ok(Box::new(mote.with_fps(self.mote.fps)))
+    }
+
+    #[cfg(feature = "python")]
+    fn build_mote(&self, name: String) -> Result<Box<dyn RgbController>> {
+        use super::mote_python::PythonMoteController;
+        if self.mote.port.is_some() {
+            eprintln!("The Python Mote backend finds the dock itself; ignoring controller.mote.port");
+        }
+        let mote = PythonMoteController::new(name, self.mote.channels.clone())?;
+        Ok(Box::new(mote.with_fps(self.mote.fps)))
+    }
+}
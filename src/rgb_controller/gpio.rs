@@ -32,4 +32,4 @@ impl RgbController for GPIOController {
     fn name(&self) -> &str {
         &self.name
     }
-} 
\ No newline at end of file
+}
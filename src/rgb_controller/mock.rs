@@ -1,23 +1,74 @@
-use super::RgbController;
+use super::{check_frame_len, Addressable, Capabilities, RgbController};
+use super::profiles::Color;
 use anyhow::Result;
+use std::time::{Duration, Instant};
+
+// Frames closer together than this are one transition or effect
+const QUIET: Duration = Duration::from_millis(250);
 
 pub struct MockController {
     name: String,
+    layout: Vec<usize>,
+    frame: Vec<Color>,
+    last_write: Option<Instant>,
 }
 
 impl MockController {
     pub fn new(name: String) -> Self {
-        Self { name }
+        Self::with_layout(name, vec![1])
+    }
+
+    /// A mock with `layout[i]` pixels on channel `i`, e.g. `[16; 4]` to stand in for a Mote.
+    pub fn with_layout(name: String, layout: Vec<usize>) -> Self {
+        let pixels = layout.iter().sum();
+        Self {
+            name,
+            layout,
+            frame: vec![Color::OFF; pixels],
+            last_write: None,
+        }
+    }
+}
+
+impl Addressable for MockController {
+    fn layout(&self) -> Vec<usize> {
+        self.layout.clone()
+    }
+
+    fn write_frame(&mut self, frame: &[Color]) -> Result<()> {
+        check_frame_len(frame, self.frame.len())?;
+        // Animations write a frame every few milliseconds, so only the
+        // first of each run is worth a line
+        if self.last_write.is_none_or(|last| last.elapsed() >= QUIET) {
+            let changed = frame.iter().zip(&self.frame).filter(|(new, old)| new != old).count();
+            println!("[{}] Writing frame, {} of {} pixels changed", self.name, changed, frame.len());
+        }
+        self.last_write = Some(Instant::now());
+        self.frame.copy_from_slice(frame);
+        Ok(())
+    }
+
+    fn frame(&self) -> &[Color] {
+        &self.frame
     }
 }
 
 impl RgbController for MockController {
     fn set_color(&mut self, red: u8, green: u8, blue: u8) -> Result<()> {
         println!("[{}] Setting color to RGB({}, {}, {})", self.name, red, green, blue);
+        self.frame.fill(Color { red, green, blue });
         Ok(())
     }
 
     fn name(&self) -> &str {
         &self.name
     }
-} 
\ No newline at end of file
+
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::addressable(&self.layout)
+    }
+}
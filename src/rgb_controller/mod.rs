@@ -1,11 +1,97 @@
 use anyhow::Result;
-use std::any::Any;
+use profiles::Color;
+use serde::{Deserialize, Serialize};
 
 pub trait RgbController: Send + Sync {
     fn set_color(&mut self, red: u8, green: u8, blue: u8) -> Result<()>;
     fn name(&self) -> &str;
-    fn as_any(&mut self) -> &mut dyn Any;
+
+    /// Per-pixel access, for controllers that have it.
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        None
+    }
+
+    /// What the device can do. The default describes a single-color device.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+}
+
+/// Reported by every controller so the daemon, CLI and tray can adapt to it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Capabilities {
+    /// Pixels can be set individually through `as_addressable`.
+    pub addressable: bool,
+    pub pixels: usize,
+    /// Independently colorable zones (channels, for addressable devices).
+    pub zones: usize,
+    /// The device dims itself, rather than having colors scaled for it.
+    pub hardware_brightness: bool,
+    /// The device can fade or animate on its own.
+    pub native_transitions: bool,
+    /// Pixels have a separate white channel.
+    pub rgbw: bool,
+    /// Frames per second the device keeps up with, if it has a known limit.
+    pub max_refresh_rate: Option<u32>,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            addressable: false,
+            pixels: 1,
+            zones: 1,
+            hardware_brightness: false,
+            native_transitions: false,
+            rgbw: false,
+            max_refresh_rate: None,
+        }
+    }
+}
+
+impl Capabilities {
+    /// An addressable device with one zone per channel of `layout`.
+    pub fn addressable(layout: &[usize]) -> Self {
+        Self {
+            addressable: true,
+            pixels: layout.iter().sum(),
+            zones: layout.len(),
+            ..Self::default()
+        }
+    }
+}
+
+/// A controller whose pixels can be set individually. Frames are laid out
+/// channel by channel, in the order `layout` lists them.
+pub trait Addressable: RgbController {
+    /// Pixels per channel.
+    fn layout(&self) -> Vec<usize>;
+
+    fn pixel_count(&self) -> usize {
+        self.layout().iter().sum()
+    }
+
+    /// Shows a whole frame in one go. `frame` must hold `pixel_count()` colors.
+    fn write_frame(&mut self, frame: &[Color]) -> Result<()>;
+
+    /// The frame currently shown.
+    fn frame(&self) -> &[Color];
+}
+
+fn check_frame_len(frame: &[Color], pixels: usize) -> Result<()> {
+    if frame.len() != pixels {
+        anyhow::bail!("frame has {} pixels, controller has {}", frame.len(), pixels);
+    }
+    Ok(())
 }
 
 pub mod profiles;
-pub mod mote; 
\ No newline at end of file
+mod named_colors;
+pub mod factory;
+pub mod mock;
+pub mod gpio;
+pub mod mote;
+pub mod openrgb;
+#[cfg(feature = "python")]
+pub mod mote_python; 
\ No newline at end of file
@@ -1,151 +1,311 @@
-use super::RgbController;
-use super::profiles::{Color, ColorSetting};
-use anyhow::{Result, Context};
-use pyo3::prelude::*;
-use std::time::Duration;
-use tokio::time::sleep;
-use std::any::Any;
-use rand::seq::SliceRandom;
-use rand::rngs::StdRng;
-use rand::SeedableRng;
-use std::env;
+// Native driver for the Pimoroni Mote USB dock.
+//
+// The dock is a USB CDC serial device. Every message starts with the ASCII
+// header `mote` followed by a command byte, mirroring the Python library:
+//
+//   `mote` `c` <channel> <num_pixels> <flags>   configure a channel (1-based)
+//   `mote` `o` <b g r>...                       show: pixels of every configured
+//                                               channel, in channel order
+//
+// Bit 0 of the configure flags enables the dock's gamma correction.
+use super::{check_frame_len, Addressable, Capabilities, RgbController};
+use super::profiles::Color;
+use anyhow::{Result, Context, bail};
+use serde::{Deserialize, Serialize};
+use serialport::{SerialPortType, TTYPort};
+use std::io::Write;
+use std::thread;
+use std::time::{Duration, Instant};
 
-pub struct MoteController {
-    name: String,
-    py_mote: PyObject,
-    current_state: [[Color; 16]; 4],  // [channel][pixel]
+/// The dock has four ports, each taking a stick of up to 16 pixels by default.
+pub const CHANNELS: usize = 4;
+pub const PIXELS_PER_CHANNEL: usize = 16;
+
+pub const USB_VID: u16 = 0x16d0;
+pub const USB_PID: u16 = 0x08c4;
+const BAUD_RATE: u32 = 115_200;
+// A frame of four full sticks is ~200 bytes, which the dock takes at about this rate.
+// The default cap on frames per second, see `with_fps`.
+pub const MAX_REFRESH_RATE: u32 = 60;
+const HEADER: &[u8] = b"mote";
+
+/// What is plugged into one port of the dock.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MoteChannel {
+    pub pixels: usize,
+    /// Let the dock apply its gamma correction table.
+    pub gamma: bool,
 }
 
-fn get_python_path() -> Option<String> {
-    env::var("VIRTUAL_ENV").ok().map(|venv_path| {
-        if cfg!(target_os = "macos") {
-            format!("{}/lib/python3.11/site-packages", venv_path)
-        } else {
-            format!("{}/lib/python3/site-packages", venv_path)
-        }
-    })
+impl Default for MoteChannel {
+    fn default() -> Self {
+        Self { pixels: PIXELS_PER_CHANNEL, gamma: false }
+    }
 }
 
-impl MoteController {
-    pub fn new(name: String) -> Result<Self> {
-        if let Some(site_packages) = get_python_path() {
-            env::set_var("PYTHONPATH", site_packages);
+impl MoteChannel {
+    /// Four full-length sticks, as the original hardware kit ships.
+    pub fn defaults() -> Vec<MoteChannel> {
+        vec![MoteChannel::default(); CHANNELS]
+    }
+
+    pub fn validate(channels: &[MoteChannel]) -> Result<()> {
+        if channels.is_empty() || channels.len() > CHANNELS {
+            bail!("the Mote dock has 1 to {} channels, not {}", CHANNELS, channels.len());
         }
-        println!("PYTHONPATH: {}", env::var("PYTHONPATH").unwrap_or_default());
-
-        Python::with_gil(|py| {
-            // Import the mote module
-            let mote_module = PyModule::import(py, "mote")
-                .context("Failed to import mote module. Is it installed?")?;
-            
-            // Create a new Mote object
-            let mote = mote_module.getattr("Mote")?.call0()?;
-            
-            // Configure all 4 strips
-            for port in 1..=4 {
-                mote.call_method1("configure_channel", (port, 16, false))?;
+        for (index, channel) in channels.iter().enumerate() {
+            if channel.pixels == 0 || channel.pixels > u8::MAX as usize {
+                bail!("Mote channel {} must have 1 to {} pixels, not {}", index + 1, u8::MAX, channel.pixels);
             }
-            
-            // Clear any existing state
-            mote.call_method0("clear")?;
-            mote.call_method0("show")?;
-            
-            // Store the configured Mote object
-            let py_mote = mote.into_py(py);
-            
-            Ok(Self { 
-                name, 
-                py_mote,
-                current_state: [[Color::OFF; 16]; 4],
-            })
-        })
-    }
-
-    async fn set_pixel(&mut self, channel: usize, pixel: usize, color: Color) -> Result<()> {
-        Python::with_gil(|py| {
-            let mote = self.py_mote.as_ref(py);
-            mote.call_method1(
-                "set_pixel",
-                (channel + 1, pixel, color.red, color.green, color.blue)
-            )?;
-            mote.call_method0("show")?;
-            Ok::<_, anyhow::Error>(())
-        })?;
-        self.current_state[channel][pixel] = color;
+        }
         Ok(())
     }
+}
 
-    pub async fn transition_to(&mut self, setting: ColorSetting) -> Result<()> {
-        let target_color = setting.color();
-        let delay = Duration::from_millis(50);
+/// Spaces frames out to at most `fps` a second, so animations don't flood
+/// the USB bus. Shared with the Python backend.
+pub(crate) struct FrameLimiter {
+    fps: u32,
+    last: Option<Instant>,
+}
 
-        // Create a sequence of pixels to update
-        let mut pixels: Vec<(usize, usize)> = Vec::new();
-        for channel in 0..4 {
-            for pixel in 0..16 {
-                pixels.push((channel, pixel));
+impl FrameLimiter {
+    pub(crate) fn new(fps: u32) -> Self {
+        Self { fps: fps.max(1), last: None }
+    }
+
+    pub(crate) fn fps(&self) -> u32 {
+        self.fps
+    }
+
+    /// Blocks until the next frame may go out. Only the device's render
+    /// thread writes frames, so sleeping here holds nobody else up.
+    pub(crate) fn wait(&mut self) {
+        let interval = Duration::from_secs(1) / self.fps;
+        if let Some(last) = self.last {
+            let elapsed = last.elapsed();
+            if elapsed < interval {
+                thread::sleep(interval - elapsed);
             }
         }
+        self.last = Some(Instant::now());
+    }
+}
+
+pub struct MoteController {
+    name: String,
+    port: TTYPort,
+    channels: Vec<MoteChannel>,
+    // Channel by channel, as sent to the dock
+    frame: Vec<Color>,
+    limiter: FrameLimiter,
+}
+
+impl MoteController {
+    /// Finds the dock by its USB ids and opens it.
+    pub fn new(name: String, channels: Vec<MoteChannel>) -> Result<Self> {
+        let path = Self::find_port()?;
+        Self::open(name, &path, channels)
+    }
+
+    pub fn open(name: String, path: &str, channels: Vec<MoteChannel>) -> Result<Self> {
+        let port = serialport::new(path, BAUD_RATE)
+            .open_native()
+            .with_context(|| format!("Failed to open Mote serial port {}", path))?;
+        Self::with_port(name, port, channels)
+    }
+
+    /// Takes over an already open port, e.g. one end of `TTYPort::pair()`.
+    pub fn with_port(name: String, port: TTYPort, channels: Vec<MoteChannel>) -> Result<Self> {
+        MoteChannel::validate(&channels)?;
+        let pixels = channels.iter().map(|channel| channel.pixels).sum();
+        let mut mote = Self {
+            name,
+            port,
+            channels,
+            frame: vec![Color::OFF; pixels],
+            limiter: FrameLimiter::new(MAX_REFRESH_RATE),
+        };
+
+        // Configure the strips that are plugged in
+        for (index, channel) in mote.channels.clone().into_iter().enumerate() {
+            mote.configure_channel(index, channel.pixels, channel.gamma)?;
+        }
+
+        // Clear any existing state
+        mote.show()?;
+        Ok(mote)
+    }
 
-        // Create a thread-safe RNG using from_entropy()
-        let mut rng = StdRng::from_entropy();
-        pixels.shuffle(&mut rng);
+    /// Caps how many frames a second go to the dock.
+    pub fn with_fps(mut self, fps: u32) -> Self {
+        self.limiter = FrameLimiter::new(fps);
+        self
+    }
 
-        // Update each pixel with a delay
-        for (channel, pixel) in pixels {
-            self.set_pixel(channel, pixel, target_color).await?;
-            sleep(delay).await;
+    pub fn find_port() -> Result<String> {
+        let ports = serialport::available_ports().context("Failed to list serial ports")?;
+        ports.into_iter()
+            .find(|port| matches!(
+                &port.port_type,
+                SerialPortType::UsbPort(usb) if usb.vid == USB_VID && usb.pid == USB_PID
+            ))
+            .map(|port| port.port_name)
+            .context("No Mote USB dock found. Is it plugged in?")
+    }
+
+    fn configure_channel(&mut self, channel: usize, pixels: usize, gamma: bool) -> Result<()> {
+        if pixels > u8::MAX as usize {
+            bail!("Mote channels hold at most {} pixels", u8::MAX);
         }
+        let mut message = HEADER.to_vec();
+        message.extend_from_slice(&[b'c', (channel + 1) as u8, pixels as u8, gamma as u8]);
+        self.port.write_all(&message)?;
+        Ok(())
+    }
 
+    fn show(&mut self) -> Result<()> {
+        let mut message = HEADER.to_vec();
+        message.push(b'o');
+        for pixel in &self.frame {
+            message.extend_from_slice(&[pixel.blue, pixel.green, pixel.red]);
+        }
+        self.port.write_all(&message)?;
+        self.port.flush()?;
         Ok(())
     }
+
+    pub fn set_pixel(&mut self, channel: usize, pixel: usize, color: Color) -> Result<()> {
+        let Some(length) = self.channels.get(channel).map(|channel| channel.pixels) else {
+            bail!("the Mote has {} channels", self.channels.len());
+        };
+        if pixel >= length {
+            bail!("Mote channel {} has {} pixels", channel + 1, length);
+        }
+        let offset: usize = self.channels[..channel].iter().map(|channel| channel.pixels).sum();
+        let mut frame = self.frame.clone();
+        frame[offset + pixel] = color;
+        self.write_frame(&frame)
+    }
+}
+
+impl Addressable for MoteController {
+    fn layout(&self) -> Vec<usize> {
+        self.channels.iter().map(|channel| channel.pixels).collect()
+    }
+
+    /// The dock only takes whole frames, so a frame is sent in one write,
+    /// and not at all if no pixel changed.
+    fn write_frame(&mut self, frame: &[Color]) -> Result<()> {
+        check_frame_len(frame, self.frame.len())?;
+        if frame == self.frame.as_slice() {
+            return Ok(());
+        }
+        self.frame.copy_from_slice(frame);
+        self.limiter.wait();
+        self.show()
+    }
+
+    fn frame(&self) -> &[Color] {
+        &self.frame
+    }
 }
 
 impl RgbController for MoteController {
     fn set_color(&mut self, red: u8, green: u8, blue: u8) -> Result<()> {
-        let color = Color { red, green, blue };
-        Python::with_gil(|py| {
-            let mote = self.py_mote.as_ref(py);
-            
-            // Update all pixels
-            for channel in 1..=4 {
-                for pixel in 0..16 {
-                    mote.call_method1(
-                        "set_pixel",
-                        (channel, pixel, color.red, color.green, color.blue)
-                    )?;
-                }
-            }
-            
-            mote.call_method0("show")?;
-            Ok::<_, anyhow::Error>(())
-        })?;
-
-        // Update state
-        for channel in 0..4 {
-            for pixel in 0..16 {
-                self.current_state[channel][pixel] = color;
-            }
-        }
-        Ok(())
+        let frame = vec![Color { red, green, blue }; self.frame.len()];
+        self.write_frame(&frame)
     }
 
     fn name(&self) -> &str {
         &self.name
     }
 
-    fn as_any(&mut self) -> &mut dyn Any {
-        self
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            max_refresh_rate: Some(self.limiter.fps()),
+            ..Capabilities::addressable(&self.layout())
+        }
     }
 }
 
 impl Drop for MoteController {
     fn drop(&mut self) {
         // Clear the LEDs when the controller is dropped
-        let _ = Python::with_gil(|py| {
-            let mote = self.py_mote.as_ref(py);
-            let _ = mote.call_method0("clear");
-            let _ = mote.call_method0("show");
-        });
+        self.frame.fill(Color::OFF);
+        let _ = self.show();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialport::SerialPort;
+    use std::io::Read;
+
+    // A dock with a stick of 2 pixels on port 1 and one of 3, gamma corrected, on port 2
+    fn open() -> (MoteController, TTYPort) {
+        let (mut dock, port) = TTYPort::pair().unwrap();
+        dock.set_timeout(Duration::from_secs(1)).unwrap();
+        let channels = vec![MoteChannel { pixels: 2, gamma: false }, MoteChannel { pixels: 3, gamma: true }];
+        let mote = MoteController::with_port("test".to_string(), port, channels).unwrap();
+        (mote, dock)
     }
-} 
\ No newline at end of file
+
+    fn read(dock: &mut TTYPort, len: usize) -> Vec<u8> {
+        let mut bytes = vec![0; len];
+        dock.read_exact(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn show(pixels: &[[u8; 3]]) -> Vec<u8> {
+        let mut message = b"moteo".to_vec();
+        message.extend(pixels.iter().flatten());
+        message
+    }
+
+    #[test]
+    fn configures_channels_and_clears() {
+        let (_mote, mut dock) = open();
+        assert_eq!(read(&mut dock, 16), b"motec\x01\x02\x00motec\x02\x03\x01");
+        assert_eq!(read(&mut dock, 5 + 5 * 3), show(&[[0; 3]; 5]));
+    }
+
+    #[test]
+    fn shows_pixels_as_bgr() {
+        let (mut mote, mut dock) = open();
+        read(&mut dock, 16 + 5 + 5 * 3);
+
+        mote.set_pixel(1, 2, Color { red: 10, green: 20, blue: 30 }).unwrap();
+        assert_eq!(read(&mut dock, 5 + 5 * 3), show(&[[0; 3], [0; 3], [0; 3], [0; 3], [30, 20, 10]]));
+        assert!(mote.set_pixel(0, 2, Color::WHITE).is_err());
+        assert!(mote.set_pixel(2, 0, Color::WHITE).is_err());
+    }
+
+    #[test]
+    fn sends_dimmed_frames_at_the_frame_rate() {
+        let (mote, mut dock) = open();
+        let mut mote = mote.with_fps(10);
+        read(&mut dock, 16 + 5 + 5 * 3);
+        assert_eq!(mote.capabilities().max_refresh_rate, Some(10));
+
+        // The engine scales frames by the brightness before they get here
+        let dimmed = Color { red: 200, green: 100, blue: 50 }.scale(0.5);
+        let start = Instant::now();
+        mote.set_color(dimmed.red, dimmed.green, dimmed.blue).unwrap();
+        assert_eq!(read(&mut dock, 5 + 5 * 3), show(&[[25, 50, 100]; 5]));
+        mote.set_color(0, 0, 1).unwrap();
+        assert_eq!(read(&mut dock, 5 + 5 * 3), show(&[[1, 0, 0]; 5]));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        // Unchanged frames are not sent at all
+        mote.set_color(0, 0, 1).unwrap();
+        mote.set_color(1, 0, 0).unwrap();
+        assert_eq!(read(&mut dock, 5 + 5 * 3), show(&[[0, 0, 1]; 5]));
+    }
+}
@@ -0,0 +1,162 @@
+// Mote backend that drives the device through the `mote` Python library.
+// Only built with the `python` feature; the native driver in `mote.rs` is the default.
+use super::{check_frame_len, Addressable, Capabilities, RgbController};
+use super::mote::{FrameLimiter, MoteChannel, MAX_REFRESH_RATE};
+use super::profiles::Color;
+use anyhow::{Result, Context};
+use pyo3::prelude::*;
+use std::env;
+
+pub struct PythonMoteController {
+    name: String,
+    py_mote: PyObject,
+    channels: Vec<MoteChannel>,
+    frame: Vec<Color>,
+    limiter: FrameLimiter,
+}
+
+fn get_python_path() -> Option<String> {
+    env::var("VIRTUAL_ENV").ok().map(|venv_path| {
+        if cfg!(target_os = "macos") {
+            format!("{}/lib/python3.11/site-packages", venv_path)
+        } else {
+            format!("{}/lib/python3/site-packages", venv_path)
+        }
+    })
+}
+
+impl PythonMoteController {
+    pub fn new(name: String, channels: Vec<MoteChannel>) -> Result<Self> {
+        MoteChannel::validate(&channels)?;
+        if let Some(site_packages) = get_python_path() {
+            env::set_var("PYTHONPATH", site_packages);
+        }
+        println!("PYTHONPATH: {}", env::var("PYTHONPATH").unwrap_or_default());
+
+        Python::with_gil(|py| {
+            // Import the mote module
+            let mote_module = PyModule::import(py, "mote")
+                .context("Failed to import mote module. Is it installed?")?;
+            
+            // Create a new Mote object
+            let mote = mote_module.getattr("Mote")?.call0()?;
+            
+            // Configure the strips that are plugged in
+            for (index, channel) in channels.iter().enumerate() {
+                mote.call_method1("configure_channel", (index + 1, channel.pixels, channel.gamma))?;
+            }
+            
+            // Clear any existing state
+            mote.call_method0("clear")?;
+            mote.call_method0("show")?;
+            
+            // Store the configured Mote object
+            let py_mote = mote.into_py(py);
+            
+            let pixels = channels.iter().map(|channel| channel.pixels).sum();
+            Ok(Self { 
+                name, 
+                py_mote,
+                channels,
+                frame: vec![Color::OFF; pixels],
+                limiter: FrameLimiter::new(MAX_REFRESH_RATE),
+            })
+        })
+    }
+
+    /// Caps how many frames a second go to the dock.
+    pub fn with_fps(mut self, fps: u32) -> Self {
+        self.limiter = FrameLimiter::new(fps);
+        self
+    }
+
+    pub fn set_pixel(&mut self, channel: usize, pixel: usize, color: Color) -> Result<()> {
+        let Some(length) = self.channels.get(channel).map(|channel| channel.pixels) else {
+            anyhow::bail!("the Mote has {} channels", self.channels.len());
+        };
+        if pixel >= length {
+            anyhow::bail!("Mote channel {} has {} pixels", channel + 1, length);
+        }
+        let offset: usize = self.channels[..channel].iter().map(|channel| channel.pixels).sum();
+        let mut frame = self.frame.clone();
+        frame[offset + pixel] = color;
+        self.write_frame(&frame)
+    }
+}
+
+impl Addressable for PythonMoteController {
+    fn layout(&self) -> Vec<usize> {
+        self.channels.iter().map(|channel| channel.pixels).collect()
+    }
+
+    /// Sends only the pixels that differ from what the dock shows, then a
+    /// single `show`, all under one GIL acquisition.
+    fn write_frame(&mut self, frame: &[Color]) -> Result<()> {
+        check_frame_len(frame, self.frame.len())?;
+        let mut changed = Vec::new();
+        let mut start = 0;
+        for (index, channel) in self.channels.iter().enumerate() {
+            for pixel in 0..channel.pixels {
+                if frame[start + pixel] != self.frame[start + pixel] {
+                    changed.push((index + 1, pixel, frame[start + pixel]));
+                }
+            }
+            start += channel.pixels;
+        }
+        if changed.is_empty() {
+            return Ok(());
+        }
+
+        self.limiter.wait();
+        Python::with_gil(|py| {
+            let mote = self.py_mote.as_ref(py);
+            for (channel, pixel, color) in changed {
+                mote.call_method1(
+                    "set_pixel",
+                    (channel, pixel, color.red, color.green, color.blue)
+                )?;
+            }
+            mote.call_method0("show")?;
+            Ok::<_, anyhow::Error>(())
+        })?;
+        self.frame.copy_from_slice(frame);
+        Ok(())
+    }
+
+    fn frame(&self) -> &[Color] {
+        &self.frame
+    }
+}
+
+impl RgbController for PythonMoteController {
+    fn set_color(&mut self, red: u8, green: u8, blue: u8) -> Result<()> {
+        let frame = vec![Color { red, green, blue }; self.frame.len()];
+        self.write_frame(&frame)
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            max_refresh_rate: Some(self.limiter.fps()),
+            ..Capabilities::addressable(&self.layout())
+        }
+    }
+}
+
+impl Drop for PythonMoteController {
+    fn drop(&mut self) {
+        // Clear the LEDs when the controller is dropped
+        Python::with_gil(|py| {
+            let mote = self.py_mote.as_ref(py);
+            let _ = mote.call_method0("clear");
+            let _ = mote.call_method0("show");
+        });
+    }
+} 
\ No newline at end of file
@@ -1,27 +1,328 @@
-use super::RgbController;
-use anyhow::Result;
+use super::{check_frame_len, Addressable, Capabilities, RgbController};
+use super::profiles::Color;
+use anyhow::{Result, Context, bail};
+use std::io::ErrorKind;
+use std::net::TcpStream;
+use std::time::Duration;
 
+pub mod protocol;
+#[cfg(test)]
+mod server;
+
+use protocol::{packet, ControllerData, ModeData, PayloadReader};
+
+const CLIENT_NAME: &str = "rgbd";
+
+// Servers older than protocol version 1 never answer the version request
+const VERSION_TIMEOUT: Duration = Duration::from_secs(1);
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Connection to an OpenRGB SDK server.
+pub struct OpenRGBClient {
+    stream: TcpStream,
+    version: u32,
+}
+
+impl OpenRGBClient {
+    pub fn connect(address: &str, port: u16, client_name: &str) -> Result<Self> {
+        let stream = TcpStream::connect((address, port))
+            .with_context(|| format!("Failed to connect to OpenRGB server at {}:{}", address, port))?;
+        stream.set_nodelay(true)?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+        let mut client = Self { stream, version: 0 };
+        client.version = client.negotiate_version()?;
+        client.send(0, packet::SET_CLIENT_NAME, format!("{}\0", client_name).as_bytes())?;
+        Ok(client)
+    }
+
+    fn negotiate_version(&mut self) -> Result<u32> {
+        self.send(0, packet::REQUEST_PROTOCOL_VERSION, &protocol::PROTOCOL_VERSION.to_le_bytes())?;
+        self.stream.set_read_timeout(Some(VERSION_TIMEOUT))?;
+        let reply = self.receive(packet::REQUEST_PROTOCOL_VERSION);
+        self.stream.set_read_timeout(Some(IO_TIMEOUT))?;
+
+        let server_version = match reply {
+            Ok(payload) => PayloadReader::new(&payload).u32()?,
+            Err(e) if is_timeout(&e) => 0,
+            Err(e) => return Err(e),
+        };
+        Ok(server_version.min(protocol::PROTOCOL_VERSION))
+    }
+
+    /// The protocol version both sides agreed on.
+    pub fn protocol_version(&self) -> u32 {
+        self.version
+    }
+
+    fn send(&mut self, device: u32, packet_id: u32, payload: &[u8]) -> Result<()> {
+        protocol::write_packet(&mut self.stream, device, packet_id, payload)
+    }
+
+    // Reads until the reply to `packet_id`, skipping unsolicited notifications
+    fn receive(&mut self, packet_id: u32) -> Result<Vec<u8>> {
+        loop {
+            let (header, payload) = protocol::read_packet(&mut self.stream)?;
+            if header.packet_id == packet_id {
+                return Ok(payload);
+            }
+            if header.packet_id != packet::DEVICE_LIST_UPDATED {
+                bail!("unexpected OpenRGB packet {} while waiting for {}", header.packet_id, packet_id);
+            }
+        }
+    }
+
+    pub fn controller_count(&mut self) -> Result<u32> {
+        self.send(0, packet::REQUEST_CONTROLLER_COUNT, &[])?;
+        let payload = self.receive(packet::REQUEST_CONTROLLER_COUNT)?;
+        PayloadReader::new(&payload).u32()
+    }
+
+    pub fn controller_data(&mut self, device: u32) -> Result<ControllerData> {
+        let request = if self.version >= 1 { self.version.to_le_bytes().to_vec() } else { Vec::new() };
+        self.send(device, packet::REQUEST_CONTROLLER_DATA, &request)?;
+        let payload = self.receive(packet::REQUEST_CONTROLLER_DATA)?;
+        ControllerData::decode(&payload, self.version)
+            .with_context(|| format!("Failed to parse OpenRGB controller {}", device))
+    }
+
+    pub fn update_leds(&mut self, device: u32, colors: &[Color]) -> Result<()> {
+        self.send(device, packet::UPDATE_LEDS, &protocol::encode_update_leds(colors))
+    }
+
+    pub fn update_zone_leds(&mut self, device: u32, zone: u32, colors: &[Color]) -> Result<()> {
+        self.send(device, packet::UPDATE_ZONE_LEDS, &protocol::encode_update_zone_leds(zone, colors))
+    }
+
+    /// Puts the device in its per-LED ("Direct") mode.
+    pub fn set_custom_mode(&mut self, device: u32) -> Result<()> {
+        self.send(device, packet::SET_CUSTOM_MODE, &[])
+    }
+
+    pub fn update_mode(&mut self, device: u32, mode_index: u32, mode: &ModeData) -> Result<()> {
+        let payload = protocol::encode_update_mode(mode_index, mode, self.version);
+        self.send(device, packet::UPDATE_MODE, &payload)
+    }
+}
+
+fn is_timeout(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<std::io::Error>()
+        .is_some_and(|e| matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut))
+}
+
+/// Drives every device managed by an OpenRGB server as one controller.
 pub struct OpenRGBController {
     name: String,
     address: String,
     port: u16,
+    client: OpenRGBClient,
+    devices: Vec<ControllerData>,
+    frame: Vec<Color>,
 }
 
 impl OpenRGBController {
-    pub fn new(name: String, address: String, port: u16) -> Self {
-        Self { name, address, port }
+    pub fn new(name: String, address: String, port: u16) -> Result<Self> {
+        let mut client = OpenRGBClient::connect(&address, port, CLIENT_NAME)?;
+        let count = client.controller_count()?;
+        let devices = (0..count)
+            .map(|device| client.controller_data(device))
+            .collect::<Result<Vec<_>>>()?;
+        println!("[{}] OpenRGB protocol v{}, {} devices at {}:{}",
+            name, client.protocol_version(), devices.len(), address, port);
+
+        // Start from whatever the devices were showing
+        let frame = devices.iter().flat_map(|device| device.colors.iter().copied()).collect();
+        let mut controller = Self { name, address, port, client, devices, frame };
+        for device in 0..controller.devices.len() as u32 {
+            controller.client.set_custom_mode(device)?;
+        }
+        Ok(controller)
+    }
+
+    pub fn devices(&self) -> &[ControllerData] {
+        &self.devices
+    }
+
+    /// Switches every device that has a mode by this name, e.g. "Static" or
+    /// "Breathing". Devices without it are left alone.
+    pub fn select_mode(&mut self, mode: &str) -> Result<()> {
+        let mut found = false;
+        for (device, data) in self.devices.iter_mut().enumerate() {
+            let Some(index) = data.mode_index(mode) else {
+                continue;
+            };
+            self.client.update_mode(device as u32, index as u32, &data.modes[index])?;
+            data.active_mode = index as i32;
+            found = true;
+        }
+        if !found {
+            bail!("no OpenRGB device at {}:{} has a mode named {:?}", self.address, self.port, mode);
+        }
+        Ok(())
+    }
+
+    pub fn set_zone_color(&mut self, device: usize, zone: usize, color: Color) -> Result<()> {
+        let data = self.devices.get(device).context("no such OpenRGB device")?;
+        let zone_data = data.zones.get(zone).context("no such OpenRGB zone")?;
+        let colors = vec![color; zone_data.leds_count as usize];
+        self.client.update_zone_leds(device as u32, zone as u32, &colors)?;
+
+        let device_start: usize = self.devices[..device].iter().map(ControllerData::led_count).sum();
+        let zone_start: u32 = data.zones[..zone].iter().map(|zone| zone.leds_count).sum();
+        let start = device_start + zone_start as usize;
+        self.frame[start..start + colors.len()].fill(color);
+        Ok(())
+    }
+}
+
+/// Each OpenRGB device is one channel of the frame.
+impl Addressable for OpenRGBController {
+    fn layout(&self) -> Vec<usize> {
+        self.devices.iter().map(ControllerData::led_count).collect()
+    }
+
+    fn write_frame(&mut self, frame: &[Color]) -> Result<()> {
+        check_frame_len(frame, self.frame.len())?;
+        let mut start = 0;
+        for (device, data) in self.devices.iter().enumerate() {
+            let colors = &frame[start..start + data.led_count()];
+            self.client.update_leds(device as u32, colors)?;
+            self.frame[start..start + colors.len()].copy_from_slice(colors);
+            start += colors.len();
+        }
+        Ok(())
+    }
+
+    fn frame(&self) -> &[Color] {
+        &self.frame
     }
 }
 
 impl RgbController for OpenRGBController {
     fn set_color(&mut self, red: u8, green: u8, blue: u8) -> Result<()> {
-        // TODO: Implement OpenRGB protocol
-        println!("[{}] Would set OpenRGB color to RGB({}, {}, {}) at {}:{}",
-            self.name, red, green, blue, self.address, self.port);
-        Ok(())
+        let frame = vec![Color { red, green, blue }; self.frame.len()];
+        self.write_frame(&frame)
     }
 
     fn name(&self) -> &str {
         &self.name
     }
-} 
\ No newline at end of file
+
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let modes = || self.devices.iter().flat_map(|device| &device.modes);
+        Capabilities {
+            hardware_brightness: modes().any(|mode| mode.brightness_max > mode.brightness_min),
+            // Breathing, rainbow and the like run on the device
+            native_transitions: modes().any(|mode| mode.speed_max != mode.speed_min),
+            ..Capabilities::addressable(&self.layout())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::protocol::ZoneData;
+    use super::server::{fake_device, StandInServer};
+    use super::*;
+
+    const RED: Color = Color { red: 255, green: 0, blue: 0 };
+    const BLUE: Color = Color { red: 0, green: 0, blue: 255 };
+
+    // A strip of 3 and a fan with two zones of 2 LEDs, with a brightness range on Static
+    fn devices() -> Vec<ControllerData> {
+        let strip = fake_device("Strip", 3);
+        let mut fan = fake_device("Fan", 4);
+        fan.zones = vec![
+            ZoneData { name: "Inner".to_string(), leds_count: 2, ..fan.zones[0].clone() },
+            ZoneData { name: "Outer".to_string(), leds_count: 2, ..fan.zones[0].clone() },
+        ];
+        fan.modes[1].brightness_max = 100;
+        vec![strip, fan]
+    }
+
+    fn connect(version: u32) -> (StandInServer, OpenRGBController) {
+        let server = StandInServer::start(devices(), version).unwrap();
+        let controller = OpenRGBController::new("test".to_string(), "127.0.0.1".to_string(), server.port()).unwrap();
+        (server, controller)
+    }
+
+    // The server handles packets in order, so a round trip means earlier
+    // updates have landed
+    fn sync(controller: &mut OpenRGBController) {
+        controller.client.controller_count().unwrap();
+    }
+
+    #[test]
+    fn sends_client_name() {
+        let (server, _controller) = connect(3);
+        assert_eq!(server.client_names(), vec![CLIENT_NAME.to_string()]);
+    }
+
+    #[test]
+    fn negotiates_version() {
+        let (_server, controller) = connect(3);
+        assert_eq!(controller.client.protocol_version(), 3);
+        assert_eq!(controller.devices()[1].modes[1].brightness_max, 100);
+        assert_eq!(controller.devices()[0].vendor, "rgbd");
+    }
+
+    #[test]
+    fn falls_back_to_version_0() {
+        let (_server, controller) = connect(0);
+        assert_eq!(controller.client.protocol_version(), 0);
+        // Neither vendors nor mode brightness exist before version 1 and 3
+        assert_eq!(controller.devices()[0].vendor, "");
+        assert_eq!(controller.devices()[1].modes[1].brightness_max, 0);
+    }
+
+    #[test]
+    fn enumerates_controllers() {
+        let (_server, controller) = connect(3);
+        let devices = controller.devices();
+        assert_eq!(devices.iter().map(|device| device.name.as_str()).collect::<Vec<_>>(), ["Strip", "Fan"]);
+        assert_eq!(devices[0].led_count(), 3);
+        assert_eq!(devices[1].zones.iter().map(|zone| zone.name.as_str()).collect::<Vec<_>>(), ["Inner", "Outer"]);
+        assert_eq!(devices[1].modes.iter().map(|mode| mode.name.as_str()).collect::<Vec<_>>(), ["Direct", "Static"]);
+        assert_eq!(devices[1].colors, vec![Color::OFF; 4]);
+    }
+
+    #[test]
+    fn switches_to_direct_mode() {
+        let (server, mut controller) = connect(3);
+        sync(&mut controller);
+        for device in 0..2 {
+            assert_eq!(server.device(device).unwrap().active_mode, 0);
+        }
+
+        controller.select_mode("static").unwrap();
+        sync(&mut controller);
+        assert_eq!(server.device(1).unwrap().active_mode, 1);
+        assert!(controller.select_mode("Rainbow").is_err());
+    }
+
+    #[test]
+    fn updates_leds() {
+        let (server, mut controller) = connect(3);
+        let frame = [RED, RED, RED, BLUE, BLUE, RED, RED];
+        controller.write_frame(&frame).unwrap();
+        sync(&mut controller);
+        assert_eq!(server.device(0).unwrap().colors, &frame[..3]);
+        assert_eq!(server.device(1).unwrap().colors, &frame[3..]);
+        assert_eq!(controller.frame(), &frame);
+        assert!(controller.write_frame(&frame[1..]).is_err());
+    }
+
+    #[test]
+    fn updates_zone_leds() {
+        let (server, mut controller) = connect(3);
+        controller.set_zone_color(1, 1, BLUE).unwrap();
+        sync(&mut controller);
+        assert_eq!(server.device(1).unwrap().colors, [Color::OFF, Color::OFF, BLUE, BLUE]);
+        assert_eq!(&controller.frame()[3..], [Color::OFF, Color::OFF, BLUE, BLUE]);
+        assert!(controller.set_zone_color(1, 2, BLUE).is_err());
+    }
+}
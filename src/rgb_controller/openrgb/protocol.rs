@@ -0,0 +1,459 @@
+// Wire format of the OpenRGB SDK protocol.
+//
+// Every packet is a 16 byte header (`ORGB`, device index, packet id, payload
+// size, all little-endian u32) followed by the payload. Strings are a u16
+// length that includes a trailing NUL, then the bytes. Colors are packed as
+// r, g, b, 0.
+use crate::rgb_controller::profiles::Color;
+use anyhow::{bail, Context, Result};
+use std::io::{Read, Write};
+
+pub const MAGIC: &[u8; 4] = b"ORGB";
+pub const HEADER_LEN: usize = 16;
+pub const DEFAULT_PORT: u16 = 6742;
+/// Largest payload we accept. Controller data for even big keyboards is a
+/// few tens of kilobytes, so anything past this is a broken or hostile server.
+pub const MAX_PACKET_LEN: u32 = 4 * 1024 * 1024;
+
+/// Highest protocol version we speak. Version 4 added zone segments, which we
+/// have no use for.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+pub mod packet {
+    pub const REQUEST_CONTROLLER_COUNT: u32 = 0;
+    pub const REQUEST_CONTROLLER_DATA: u32 = 1;
+    pub const REQUEST_PROTOCOL_VERSION: u32 = 40;
+    pub const SET_CLIENT_NAME: u32 = 50;
+    pub const DEVICE_LIST_UPDATED: u32 = 100;
+    pub const UPDATE_LEDS: u32 = 1050;
+    pub const UPDATE_ZONE_LEDS: u32 = 1051;
+    pub const SET_CUSTOM_MODE: u32 = 1100;
+    pub const UPDATE_MODE: u32 = 1101;
+}
+
+pub const MODE_FLAG_HAS_PER_LED_COLOR: u32 = 1 << 5;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Header {
+    pub device: u32,
+    pub packet_id: u32,
+    pub size: u32,
+}
+
+pub fn write_packet<W: Write>(writer: &mut W, device: u32, packet_id: u32, payload: &[u8]) -> Result<()> {
+    let mut packet = Vec::with_capacity(HEADER_LEN + payload.len());
+    packet.extend_from_slice(MAGIC);
+    packet.extend_from_slice(&device.to_le_bytes());
+    packet.extend_from_slice(&packet_id.to_le_bytes());
+    packet.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    packet.extend_from_slice(payload);
+    writer.write_all(&packet)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn read_packet<R: Read>(reader: &mut R) -> Result<(Header, Vec<u8>)> {
+    let mut header = [0u8; HEADER_LEN];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != MAGIC {
+        bail!("bad OpenRGB packet magic {:?}", &header[..4]);
+    }
+    let field = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap());
+    let header = Header {
+        device: field(4),
+        packet_id: field(8),
+        size: field(12),
+    };
+    if header.size > MAX_PACKET_LEN {
+        bail!("OpenRGB packet of {} bytes is over the {} byte limit", header.size, MAX_PACKET_LEN);
+    }
+    let mut payload = vec![0u8; header.size as usize];
+    reader.read_exact(&mut payload)?;
+    Ok((header, payload))
+}
+
+#[derive(Default)]
+pub struct PayloadWriter {
+    data: Vec<u8>,
+}
+
+impl PayloadWriter {
+    pub fn u16(&mut self, value: u16) -> &mut Self {
+        self.data.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.data.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn i32(&mut self, value: i32) -> &mut Self {
+        self.data.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn string(&mut self, value: &str) -> &mut Self {
+        self.u16(value.len() as u16 + 1);
+        self.data.extend_from_slice(value.as_bytes());
+        self.data.push(0);
+        self
+    }
+
+    pub fn color(&mut self, color: Color) -> &mut Self {
+        self.data.extend_from_slice(&[color.red, color.green, color.blue, 0]);
+        self
+    }
+
+    pub fn colors(&mut self, colors: &[Color]) -> &mut Self {
+        self.u16(colors.len() as u16);
+        for &color in colors {
+            self.color(color);
+        }
+        self
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Prefixes the payload with its own size, as most OpenRGB blocks are.
+    pub fn finish_sized(self) -> Vec<u8> {
+        let mut data = ((self.data.len() + 4) as u32).to_le_bytes().to_vec();
+        data.extend_from_slice(&self.data);
+        data
+    }
+}
+
+pub struct PayloadReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PayloadReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos + len)
+            .with_context(|| format!("OpenRGB payload truncated at byte {}", self.pos))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Bytes left to read.
+    pub fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.pos)
+    }
+
+    pub fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn string(&mut self) -> Result<String> {
+        let len = self.u16()? as usize;
+        let bytes = self.take(len)?;
+        let bytes = bytes.strip_suffix(&[0]).unwrap_or(bytes);
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    pub fn color(&mut self) -> Result<Color> {
+        let bytes = self.take(4)?;
+        Ok(Color { red: bytes[0], green: bytes[1], blue: bytes[2] })
+    }
+
+    pub fn colors(&mut self) -> Result<Vec<Color>> {
+        let count = self.u16()?;
+        (0..count).map(|_| self.color()).collect()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ModeData {
+    pub name: String,
+    pub value: i32,
+    pub flags: u32,
+    pub speed_min: u32,
+    pub speed_max: u32,
+    pub brightness_min: u32,
+    pub brightness_max: u32,
+    pub colors_min: u32,
+    pub colors_max: u32,
+    pub speed: u32,
+    pub brightness: u32,
+    pub direction: u32,
+    pub color_mode: u32,
+    pub colors: Vec<Color>,
+}
+
+impl ModeData {
+    pub fn decode(reader: &mut PayloadReader, version: u32) -> Result<Self> {
+        let mut mode = ModeData {
+            name: reader.string()?,
+            value: reader.i32()?,
+            flags: reader.u32()?,
+            speed_min: reader.u32()?,
+            speed_max: reader.u32()?,
+            ..Default::default()
+        };
+        if version >= 3 {
+            mode.brightness_min = reader.u32()?;
+            mode.brightness_max = reader.u32()?;
+        }
+        mode.colors_min = reader.u32()?;
+        mode.colors_max = reader.u32()?;
+        mode.speed = reader.u32()?;
+        if version >= 3 {
+            mode.brightness = reader.u32()?;
+        }
+        mode.direction = reader.u32()?;
+        mode.color_mode = reader.u32()?;
+        mode.colors = reader.colors()?;
+        Ok(mode)
+    }
+
+    pub fn encode(&self, writer: &mut PayloadWriter, version: u32) {
+        writer.string(&self.name).i32(self.value).u32(self.flags)
+            .u32(self.speed_min).u32(self.speed_max);
+        if version >= 3 {
+            writer.u32(self.brightness_min).u32(self.brightness_max);
+        }
+        writer.u32(self.colors_min).u32(self.colors_max).u32(self.speed);
+        if version >= 3 {
+            writer.u32(self.brightness);
+        }
+        writer.u32(self.direction).u32(self.color_mode).colors(&self.colors);
+    }
+
+    /// Whether the mode takes colors per LED, i.e. `UPDATELEDS` shows up.
+    pub fn is_direct(&self) -> bool {
+        self.flags & MODE_FLAG_HAS_PER_LED_COLOR != 0
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ZoneData {
+    pub name: String,
+    pub zone_type: i32,
+    pub leds_min: u32,
+    pub leds_max: u32,
+    pub leds_count: u32,
+    /// Matrix zones only: (height, width, LED index per cell).
+    pub matrix: Option<(u32, u32, Vec<u32>)>,
+}
+
+impl ZoneData {
+    fn decode(reader: &mut PayloadReader) -> Result<Self> {
+        let mut zone = ZoneData {
+            name: reader.string()?,
+            zone_type: reader.i32()?,
+            leds_min: reader.u32()?,
+            leds_max: reader.u32()?,
+            leds_count: reader.u32()?,
+            matrix: None,
+        };
+        if reader.u16()? > 0 {
+            let height = reader.u32()?;
+            let width = reader.u32()?;
+            // Each cell is a u32; a matrix bigger than the payload is corrupt
+            let cells = height.checked_mul(width)
+                .filter(|&cells| cells as usize <= reader.remaining() / 4)
+                .with_context(|| format!("OpenRGB zone matrix of {}x{} does not fit the payload", height, width))?;
+            let map = (0..cells).map(|_| reader.u32()).collect::<Result<_>>()?;
+            zone.matrix = Some((height, width, map));
+        }
+        Ok(zone)
+    }
+
+    fn encode(&self, writer: &mut PayloadWriter) {
+        writer.string(&self.name).i32(self.zone_type)
+            .u32(self.leds_min).u32(self.leds_max).u32(self.leds_count);
+        match &self.matrix {
+            Some((height, width, map)) => {
+                writer.u16(((map.len() + 2) * 4) as u16).u32(*height).u32(*width);
+                for &cell in map {
+                    writer.u32(cell);
+                }
+            }
+            None => {
+                writer.u16(0);
+            }
+        }
+    }
+}
+
+/// Description of one device, as returned by `REQUEST_CONTROLLER_DATA`.
+#[derive(Debug, Clone, Default)]
+pub struct ControllerData {
+    pub device_type: i32,
+    pub name: String,
+    pub vendor: String,
+    pub description: String,
+    pub version: String,
+    pub serial: String,
+    pub location: String,
+    pub active_mode: i32,
+    pub modes: Vec<ModeData>,
+    pub zones: Vec<ZoneData>,
+    pub leds: Vec<(String, u32)>,
+    pub colors: Vec<Color>,
+}
+
+impl ControllerData {
+    pub fn decode(data: &[u8], version: u32) -> Result<Self> {
+        let mut reader = PayloadReader::new(data);
+        let _size = reader.u32()?;
+        let device_type = reader.i32()?;
+        let name = reader.string()?;
+        let vendor = if version >= 1 { reader.string()? } else { String::new() };
+        let mut device = ControllerData {
+            device_type,
+            name,
+            vendor,
+            description: reader.string()?,
+            version: reader.string()?,
+            serial: reader.string()?,
+            location: reader.string()?,
+            ..Default::default()
+        };
+
+        let mode_count = reader.u16()?;
+        device.active_mode = reader.i32()?;
+        for _ in 0..mode_count {
+            device.modes.push(ModeData::decode(&mut reader, version)?);
+        }
+
+        let zone_count = reader.u16()?;
+        for _ in 0..zone_count {
+            device.zones.push(ZoneData::decode(&mut reader)?);
+        }
+
+        let led_count = reader.u16()?;
+        for _ in 0..led_count {
+            device.leds.push((reader.string()?, reader.u32()?));
+        }
+        device.colors = reader.colors()?;
+        // Frames are sized by the LEDs but written as colors
+        if device.colors.len() != device.leds.len() {
+            bail!("controller has {} LEDs but {} colors", device.leds.len(), device.colors.len());
+        }
+        Ok(device)
+    }
+
+    pub fn encode(&self, version: u32) -> Vec<u8> {
+        let mut writer = PayloadWriter::default();
+        writer.i32(self.device_type).string(&self.name);
+        if version >= 1 {
+            writer.string(&self.vendor);
+        }
+        writer.string(&self.description).string(&self.version)
+            .string(&self.serial).string(&self.location);
+
+        writer.u16(self.modes.len() as u16).i32(self.active_mode);
+        for mode in &self.modes {
+            mode.encode(&mut writer, version);
+        }
+
+        writer.u16(self.zones.len() as u16);
+        for zone in &self.zones {
+            zone.encode(&mut writer);
+        }
+
+        writer.u16(self.leds.len() as u16);
+        for (name, value) in &self.leds {
+            writer.string(name).u32(*value);
+        }
+        writer.colors(&self.colors);
+        writer.finish_sized()
+    }
+
+    pub fn led_count(&self) -> usize {
+        self.leds.len()
+    }
+
+    pub fn mode_index(&self, name: &str) -> Option<usize> {
+        self.modes.iter().position(|mode| mode.name.eq_ignore_ascii_case(name))
+    }
+}
+
+pub fn encode_update_leds(colors: &[Color]) -> Vec<u8> {
+    let mut writer = PayloadWriter::default();
+    writer.colors(colors);
+    writer.finish_sized()
+}
+
+pub fn encode_update_zone_leds(zone: u32, colors: &[Color]) -> Vec<u8> {
+    let mut writer = PayloadWriter::default();
+    writer.u32(zone).colors(colors);
+    writer.finish_sized()
+}
+
+pub fn encode_update_mode(mode_index: u32, mode: &ModeData, version: u32) -> Vec<u8> {
+    let mut writer = PayloadWriter::default();
+    writer.u32(mode_index);
+    mode.encode(&mut writer, version);
+    writer.finish_sized()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zone_payload(height: u32, width: u32, cells: usize) -> Vec<u8> {
+        let mut writer = PayloadWriter::default();
+        writer.string("Keys").i32(2).u32(4).u32(4).u32(4)
+            .u16(((cells + 2) * 4) as u16).u32(height).u32(width);
+        for cell in 0..cells {
+            writer.u32(cell as u32);
+        }
+        writer.finish()
+    }
+
+    #[test]
+    fn decodes_matrix_zone() {
+        let payload = zone_payload(2, 2, 4);
+        let zone = ZoneData::decode(&mut PayloadReader::new(&payload)).unwrap();
+        assert_eq!(zone.matrix, Some((2, 2, vec![0, 1, 2, 3])));
+    }
+
+    #[test]
+    fn rejects_oversized_matrix() {
+        for (height, width) in [(u32::MAX, u32::MAX), (3, 2), (65536, 65536)] {
+            let payload = zone_payload(height, width, 4);
+            assert!(ZoneData::decode(&mut PayloadReader::new(&payload)).is_err(), "{}x{}", height, width);
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_packets() {
+        let mut packet = MAGIC.to_vec();
+        for field in [0, packet::REQUEST_CONTROLLER_DATA, MAX_PACKET_LEN + 1] {
+            packet.extend_from_slice(&field.to_le_bytes());
+        }
+        let error = read_packet(&mut packet.as_slice()).unwrap_err();
+        assert!(error.to_string().contains("limit"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_leds_without_colors() {
+        let mut device = ControllerData {
+            leds: vec![("LED 1".to_string(), 0), ("LED 2".to_string(), 0)],
+            colors: vec![Color::OFF; 2],
+            ..Default::default()
+        };
+        let decoded = ControllerData::decode(&device.encode(PROTOCOL_VERSION), PROTOCOL_VERSION).unwrap();
+        assert_eq!(decoded.led_count(), 2);
+
+        device.colors.pop();
+        assert!(ControllerData::decode(&device.encode(PROTOCOL_VERSION), PROTOCOL_VERSION).is_err());
+    }
+}
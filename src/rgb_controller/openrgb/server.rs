@@ -0,0 +1,173 @@
+// A small in-process OpenRGB SDK server, for exercising the client without
+// real hardware. It answers the same packets OpenRGB does and keeps the LED
+// colors and active mode of a set of fake devices.
+use super::protocol::{self, packet, ControllerData, ModeData, PayloadReader, ZoneData};
+use crate::rgb_controller::profiles::Color;
+use anyhow::{Result, bail};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+struct ServerState {
+    devices: Vec<ControllerData>,
+    client_names: Vec<String>,
+}
+
+pub struct StandInServer {
+    port: u16,
+    state: Arc<Mutex<ServerState>>,
+}
+
+impl StandInServer {
+    /// Listens on an ephemeral localhost port, speaking up to `version`.
+    /// Version 0 emulates servers that ignore the version request.
+    pub fn start(devices: Vec<ControllerData>, version: u32) -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let state = Arc::new(Mutex::new(ServerState {
+            devices,
+            client_names: Vec::new(),
+        }));
+
+        let server_state = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = server_state.clone();
+                thread::spawn(move || {
+                    if let Err(e) = serve(stream, state, version) {
+                        eprintln!("OpenRGB stand-in connection closed: {}", e);
+                    }
+                });
+            }
+        });
+
+        Ok(Self { port, state })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Snapshot of a device as the server currently sees it.
+    pub fn device(&self, index: usize) -> Option<ControllerData> {
+        self.state.lock().unwrap().devices.get(index).cloned()
+    }
+
+    pub fn client_names(&self) -> Vec<String> {
+        self.state.lock().unwrap().client_names.clone()
+    }
+}
+
+fn serve(mut stream: TcpStream, state: Arc<Mutex<ServerState>>, server_version: u32) -> Result<()> {
+    let mut version = 0;
+    loop {
+        let (header, payload) = match protocol::read_packet(&mut stream) {
+            Ok(packet) => packet,
+            // Client hung up
+            Err(e) if e.downcast_ref::<std::io::Error>()
+                .is_some_and(|e| e.kind() == std::io::ErrorKind::UnexpectedEof) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let device = header.device as usize;
+        let mut reader = PayloadReader::new(&payload);
+        let mut state = state.lock().unwrap();
+
+        match header.packet_id {
+            packet::REQUEST_PROTOCOL_VERSION => {
+                if server_version == 0 {
+                    continue;
+                }
+                version = reader.u32()?.min(server_version);
+                protocol::write_packet(&mut stream, 0, header.packet_id, &server_version.to_le_bytes())?;
+            }
+            packet::SET_CLIENT_NAME => {
+                let name = String::from_utf8_lossy(&payload);
+                state.client_names.push(name.trim_end_matches('\0').to_string());
+            }
+            packet::REQUEST_CONTROLLER_COUNT => {
+                let count = state.devices.len() as u32;
+                protocol::write_packet(&mut stream, 0, header.packet_id, &count.to_le_bytes())?;
+            }
+            packet::REQUEST_CONTROLLER_DATA => {
+                let Some(data) = state.devices.get(device) else {
+                    bail!("client asked for unknown device {}", device);
+                };
+                protocol::write_packet(&mut stream, header.device, header.packet_id, &data.encode(version))?;
+            }
+            packet::UPDATE_LEDS => {
+                let _size = reader.u32()?;
+                let colors = reader.colors()?;
+                if let Some(data) = state.devices.get_mut(device) {
+                    let len = colors.len().min(data.colors.len());
+                    data.colors[..len].copy_from_slice(&colors[..len]);
+                }
+            }
+            packet::UPDATE_ZONE_LEDS => {
+                let _size = reader.u32()?;
+                let zone = reader.u32()? as usize;
+                let colors = reader.colors()?;
+                if let Some(data) = state.devices.get_mut(device) {
+                    if zone >= data.zones.len() {
+                        bail!("client updated unknown zone {} of device {}", zone, device);
+                    }
+                    let start: u32 = data.zones[..zone].iter().map(|zone| zone.leds_count).sum();
+                    let start = (start as usize).min(data.colors.len());
+                    let end = (start + colors.len()).min(data.colors.len());
+                    data.colors[start..end].copy_from_slice(&colors[..end - start]);
+                }
+            }
+            packet::SET_CUSTOM_MODE => {
+                if let Some(data) = state.devices.get_mut(device) {
+                    if let Some(index) = data.modes.iter().position(ModeData::is_direct) {
+                        data.active_mode = index as i32;
+                    }
+                }
+            }
+            packet::UPDATE_MODE => {
+                let _size = reader.u32()?;
+                let index = reader.u32()? as usize;
+                let mode = ModeData::decode(&mut reader, version)?;
+                if let Some(data) = state.devices.get_mut(device) {
+                    if index < data.modes.len() {
+                        data.modes[index] = mode;
+                        data.active_mode = index as i32;
+                    }
+                }
+            }
+            other => bail!("stand-in server does not handle packet {}", other),
+        }
+    }
+}
+
+/// A single-zone device with "Direct" and "Static" modes, starting in Static.
+pub fn fake_device(name: &str, leds: u32) -> ControllerData {
+    let mode = |name: &str, value, flags| ModeData {
+        name: name.to_string(),
+        value,
+        flags,
+        color_mode: 1,
+        ..Default::default()
+    };
+    ControllerData {
+        name: name.to_string(),
+        vendor: "rgbd".to_string(),
+        description: "OpenRGB stand-in device".to_string(),
+        location: "stand-in".to_string(),
+        active_mode: 1,
+        modes: vec![
+            mode("Direct", 0, protocol::MODE_FLAG_HAS_PER_LED_COLOR),
+            mode("Static", 1, 0),
+        ],
+        zones: vec![ZoneData {
+            name: "Strip".to_string(),
+            zone_type: 1,
+            leds_min: leds,
+            leds_max: leds,
+            leds_count: leds,
+            matrix: None,
+        }],
+        leds: (0..leds).map(|i| (format!("LED {}", i + 1), i)).collect(),
+        colors: vec![Color::OFF; leds as usize],
+        ..Default::default()
+    }
+}
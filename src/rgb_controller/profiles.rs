@@ -1,7 +1,19 @@
+use crate::effects::EffectParams;
+use crate::layout::Layout;
+use super::named_colors::NAMED_COLORS;
 use serde::{Serialize, Deserialize};
-use clap::ValueEnum;
+use anyhow::{Result, Context, bail};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::Duration;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// The range `Color::from_kelvin` covers.
+pub const MIN_KELVIN: u32 = 1000;
+pub const MAX_KELVIN: u32 = 40000;
+
+/// Deserializes from `{ red, green, blue }` or from any string `from_str` takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "ColorInput")]
 pub struct Color {
     pub red: u8,
     pub green: u8,
@@ -12,44 +24,462 @@ impl Color {
     pub const OFF: Color = Color { red: 0, green: 0, blue: 0 };
     pub const RED: Color = Color { red: 255, green: 0, blue: 0 };
     pub const WHITE: Color = Color { red: 255, green: 255, blue: 255 };
+
+    pub fn scale(self, factor: f32) -> Color {
+        let factor = factor.clamp(0.0, 1.0);
+        let channel = |value: u8| (value as f32 * factor).round() as u8;
+        Color {
+            red: channel(self.red),
+            green: channel(self.green),
+            blue: channel(self.blue),
+        }
+    }
+
+    pub fn is_off(&self) -> bool {
+        *self == Color::OFF
+    }
+
+    /// The color of a black body at this temperature, 1000K to 40000K.
+    pub fn from_kelvin(kelvin: f32) -> Color {
+        // Tanner Helland's fit to the CIE 1964 blackbody data
+        let t = kelvin.clamp(MIN_KELVIN as f32, MAX_KELVIN as f32) / 100.0;
+        let red = if t <= 66.0 { 255.0 } else { 329.69873 * (t - 60.0).powf(-0.13320476) };
+        let green = if t <= 66.0 {
+            99.4708 * t.ln() - 161.11957
+        } else {
+            288.12216 * (t - 60.0).powf(-0.07551485)
+        };
+        let blue = if t >= 66.0 {
+            255.0
+        } else if t <= 19.0 {
+            0.0
+        } else {
+            138.51773 * (t - 10.0).ln() - 305.0448
+        };
+        Color::from_unit([red / 255.0, green / 255.0, blue / 255.0])
+    }
+
+    /// Scales each channel by the device's white point: the color it has to be
+    /// sent to look neutral white.
+    pub fn balance(self, white_point: Color) -> Color {
+        let channel = |value: u8, white: u8| (value as u16 * white as u16 / 255) as u8;
+        Color {
+            red: channel(self.red, white_point.red),
+            green: channel(self.green, white_point.green),
+            blue: channel(self.blue, white_point.blue),
+        }
+    }
+
+    /// Blends towards `other` in Oklab, so fades hold an even perceived
+    /// brightness instead of dipping through muddy greys.
+    pub fn mix(self, other: Color, t: f32) -> Color {
+        let [l1, a1, b1] = self.to_oklab();
+        let [l2, a2, b2] = other.to_oklab();
+        let lerp = |from: f32, to: f32| from + (to - from) * t;
+        Color::from_oklab([lerp(l1, l2), lerp(a1, a2), lerp(b1, b2)])
+    }
+
+    // Björn Ottosson's matrices, from linear sRGB
+    fn to_oklab(self) -> [f32; 3] {
+        let [r, g, b] = [self.red, self.green, self.blue].map(|value| srgb_to_linear(value as f32 / 255.0));
+        let l = (0.41222147 * r + 0.53633254 * g + 0.05144599 * b).cbrt();
+        let m = (0.2119035 * r + 0.6806995 * g + 0.10739696 * b).cbrt();
+        let s = (0.08830246 * r + 0.2817188 * g + 0.6299787 * b).cbrt();
+        [
+            0.21045426 * l + 0.7936178 * m - 0.00407205 * s,
+            1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+            0.02590404 * l + 0.78277177 * m - 0.80867577 * s,
+        ]
+    }
+
+    fn from_oklab([lightness, a, b]: [f32; 3]) -> Color {
+        let l = (lightness + 0.39633778 * a + 0.21580376 * b).powi(3);
+        let m = (lightness - 0.10556135 * a - 0.06385417 * b).powi(3);
+        let s = (lightness - 0.08948418 * a - 1.2914855 * b).powi(3);
+        Color::from_unit([
+            4.0767417 * l - 3.3077116 * m + 0.23096993 * s,
+            -1.268438 * l + 2.6097574 * m - 0.3413194 * s,
+            -0.00419609 * l - 0.7034186 * m + 1.7076147 * s,
+        ].map(linear_to_srgb))
+    }
+
+    /// Hue in degrees, saturation and value from 0 to 1.
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> Color {
+        let chroma = value * saturation;
+        Color::from_hue(hue, chroma, value - chroma)
+    }
+
+    /// Hue in degrees, saturation and lightness from 0 to 1.
+    pub fn from_hsl(hue: f32, saturation: f32, lightness: f32) -> Color {
+        let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+        Color::from_hue(hue, chroma, lightness - chroma / 2.0)
+    }
+
+    fn from_hue(hue: f32, chroma: f32, min: f32) -> Color {
+        let sector = hue.rem_euclid(360.0) / 60.0;
+        let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+        let [r, g, b] = match sector as u32 {
+            0 => [chroma, x, 0.0],
+            1 => [x, chroma, 0.0],
+            2 => [0.0, chroma, x],
+            3 => [0.0, x, chroma],
+            4 => [x, 0.0, chroma],
+            _ => [chroma, 0.0, x],
+        };
+        Color::from_unit([r + min, g + min, b + min])
+    }
+
+    fn from_unit([red, green, blue]: [f32; 3]) -> Color {
+        let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        Color {
+            red: channel(red),
+            green: channel(green),
+            blue: channel(blue),
+        }
+    }
 }
 
-// CLI-friendly enum for predefined profiles
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, ValueEnum)]
-pub enum Profile {
-    Off,
-    Red,
-    White,
+impl FromStr for Color {
+    type Err = anyhow::Error;
+
+    /// Takes `#ff8800` or `f80`, CSS names like `orange`, `rgb(255, 136, 0)`
+    /// or `rgb(100%, 53%, 0%)`, `hsl(32, 100%, 50%)`, `hsv(32, 100%, 100%)`
+    /// and temperatures like `2700K`.
+    fn from_str(s: &str) -> Result<Self> {
+        let text = s.trim().to_ascii_lowercase();
+
+        if let Some((function, args)) = text.strip_suffix(')').and_then(|text| text.split_once('(')) {
+            let args: Vec<&str> = args.split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|arg| !arg.is_empty())
+                .collect();
+            let [first, second, third] = args[..] else {
+                bail!("{}() takes three values, got {:?}", function.trim(), s);
+            };
+            return match function.trim() {
+                "rgb" => Ok(Color {
+                    red: parse_channel(first)?,
+                    green: parse_channel(second)?,
+                    blue: parse_channel(third)?,
+                }),
+                "hsl" => Ok(Color::from_hsl(parse_hue(first)?, parse_percent(second)?, parse_percent(third)?)),
+                "hsv" | "hsb" => Ok(Color::from_hsv(parse_hue(first)?, parse_percent(second)?, parse_percent(third)?)),
+                other => bail!("unknown color function {:?}; use rgb(), hsl() or hsv()", other),
+            };
+        }
+
+        if let Some(kelvin) = text.strip_suffix('k').and_then(|kelvin| kelvin.trim().parse::<f32>().ok()) {
+            if !(MIN_KELVIN as f32..=MAX_KELVIN as f32).contains(&kelvin) {
+                bail!("color temperatures go from {}K to {}K, not {}K", MIN_KELVIN, MAX_KELVIN, kelvin);
+            }
+            return Ok(Color::from_kelvin(kelvin));
+        }
+
+        let name: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+        if let Some((_, [red, green, blue])) = NAMED_COLORS.iter().find(|(known, _)| *known == name) {
+            return Ok(Color { red: *red, green: *green, blue: *blue });
+        }
+
+        let hex = text.strip_prefix('#').unwrap_or(&text);
+        if hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).unwrap();
+            let byte = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+            match hex.len() {
+                3 => return Ok(Color { red: digit(0) * 17, green: digit(1) * 17, blue: digit(2) * 17 }),
+                6 => return Ok(Color { red: byte(0), green: byte(2), blue: byte(4) }),
+                _ => {}
+            }
+        }
+
+        bail!("unknown color {:?}; try a name, #rrggbb, rgb(), hsl(), hsv() or a temperature like 2700K", s)
+    }
 }
 
-// Internal representation that can handle custom colors
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-pub enum ColorSetting {
-    Profile(Profile),
-    Custom(Color),
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorInput {
+    Channels { red: u8, green: u8, blue: u8 },
+    Text(String),
 }
 
-impl Profile {
-    pub fn color(&self) -> Color {
-        match self {
-            Profile::Off => Color::OFF,
-            Profile::Red => Color::RED,
-            Profile::White => Color::WHITE,
+impl TryFrom<ColorInput> for Color {
+    type Error = anyhow::Error;
+
+    fn try_from(input: ColorInput) -> Result<Self> {
+        match input {
+            ColorInput::Channels { red, green, blue } => Ok(Color { red, green, blue }),
+            ColorInput::Text(text) => text.parse(),
         }
     }
 }
 
-impl ColorSetting {
-    pub fn color(&self) -> Color {
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    let value = value.clamp(0.0, 1.0);
+    if value <= 0.0031308 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 }
+}
+
+fn parse_number(arg: &str, max: f32) -> Result<f32> {
+    let value: f32 = arg.parse().with_context(|| format!("{:?} is not a number", arg))?;
+    if !(0.0..=max).contains(&value) {
+        bail!("{} is out of range 0 to {}", value, max);
+    }
+    Ok(value)
+}
+
+// An rgb() channel, written as `0` to `255` or `0%` to `100%`
+fn parse_channel(arg: &str) -> Result<u8> {
+    let value = match arg.strip_suffix('%') {
+        Some(percent) => parse_number(percent, 100.0)? * 2.55,
+        None => parse_number(arg, 255.0)?,
+    };
+    Ok(value.round() as u8)
+}
+
+fn parse_hue(arg: &str) -> Result<f32> {
+    let degrees = arg.strip_suffix("deg").unwrap_or(arg);
+    degrees.parse().ok().filter(|degrees: &f32| degrees.is_finite())
+        .with_context(|| format!("{:?} is not a hue in degrees", arg))
+}
+
+// Saturation, lightness and value, written as `50%` or just `50`
+fn parse_percent(arg: &str) -> Result<f32> {
+    Ok(parse_number(arg.strip_suffix('%').unwrap_or(arg), 100.0)? / 100.0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Transition {
+    Instant,
+    /// Flip one pixel at a time, in random order.
+    #[default]
+    Dissolve,
+    /// Blend every pixel at once, through Oklab.
+    Fade,
+    /// Flip one pixel at a time, left to right across zones placed with
+    /// `from` and `to`, otherwise along the strip.
+    Wipe,
+}
+
+/// How a transition's progress follows the clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Easing {
+    Linear,
+    /// Slow at both ends, along a sine curve.
+    #[default]
+    EaseInOut,
+    /// Like `EaseInOut`, with a steeper middle.
+    Cubic,
+}
+
+impl Easing {
+    /// Maps time, from 0 to 1, to progress from 0 to 1.
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
         match self {
-            ColorSetting::Profile(profile) => profile.color(),
-            ColorSetting::Custom(color) => *color,
+            Easing::Linear => t,
+            Easing::EaseInOut => (1.0 - (std::f32::consts::PI * t).cos()) / 2.0,
+            Easing::Cubic if t < 0.5 => 4.0 * t * t * t,
+            Easing::Cubic => 1.0 - (2.0 - 2.0 * t).powi(3) / 2.0,
+        }
+    }
+}
+
+/// A transition with any of its parts left to the defaults. Sent along with
+/// a command, and read from the `[transition]` table of the config.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransitionSpec {
+    pub style: Option<Transition>,
+    pub duration_ms: Option<u64>,
+    pub easing: Option<Easing>,
+}
+
+impl TransitionSpec {
+    pub fn style(style: Transition) -> Self {
+        Self { style: Some(style), ..Self::default() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Fills in whatever this leaves unset from `fallback`.
+    pub fn or(self, fallback: TransitionSpec) -> TransitionSpec {
+        TransitionSpec {
+            style: self.style.or(fallback.style),
+            duration_ms: self.duration_ms.or(fallback.duration_ms),
+            easing: self.easing.or(fallback.easing),
         }
     }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration_ms.map(Duration::from_millis)
+    }
 }
 
-impl From<Profile> for ColorSetting {
-    fn from(profile: Profile) -> Self {
-        ColorSetting::Profile(profile)
+// A named profile from the config file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    #[serde(default)]
+    pub color: Option<Color>,
+    /// Colors for individual zones, painted over `color`. Zones come from the
+    /// layout config, or are whole channels: `channel-1`, `channel-2`, ...
+    #[serde(default)]
+    pub zones: BTreeMap<String, Color>,
+    /// Global brightness to switch to along with the profile, from 0 to 1.
+    /// Left as it is when unset.
+    #[serde(default)]
+    pub brightness: Option<f32>,
+    /// Style to switch to this profile with, unless the request picks one.
+    #[serde(default)]
+    pub transition: Option<Transition>,
+}
+
+impl Profile {
+    pub fn new(name: &str, color: Color) -> Self {
+        Self {
+            name: name.to_string(),
+            color: Some(color),
+            zones: BTreeMap::new(),
+            brightness: None,
+            transition: None,
+        }
+    }
+
+    /// The profiles used when there is no config file.
+    pub fn builtin() -> Vec<Profile> {
+        vec![
+            Profile::new("Off", Color::OFF),
+            Profile::new("Red", Color::RED),
+            Profile::new("White", Color::WHITE),
+        ]
+    }
+
+    pub fn is_off(&self) -> bool {
+        self.brightness.is_some_and(|brightness| brightness <= 0.0)
+            || (self.color.unwrap_or(Color::OFF).is_off() && self.zones.values().all(Color::is_off))
     }
-} 
\ No newline at end of file
+
+    /// The profile's color with zones left out, for devices that show one color.
+    pub fn base_color(&self) -> Color {
+        self.color.unwrap_or(Color::OFF)
+    }
+
+    /// Builds a frame for `device`, which has the given pixels per channel.
+    /// Zones on other devices are skipped.
+    pub fn render(&self, device: &str, channels: &[usize], layout: &Layout) -> Result<Vec<Color>> {
+        let base = self.base_color();
+        let mut frame = vec![base; channels.iter().sum()];
+
+        for (zone, color) in &self.zones {
+            let pixels = layout.pixels(zone, device, channels)
+                .with_context(|| format!("profile {:?} can't paint zone {:?}", self.name, zone))?;
+            for index in pixels {
+                frame[index] = *color;
+            }
+        }
+        Ok(frame)
+    }
+}
+
+// Internal representation that can handle custom colors
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ColorSetting {
+    Profile(String),
+    Custom(Color),
+    /// White at a color temperature in Kelvin, corrected by each device's white point.
+    Temperature(u32),
+    /// An animation from `effects`, running until something else is shown.
+    Effect { name: String, params: EffectParams },
+    /// Color temperature and brightness following the sun, along the
+    /// configured `circadian` curve.
+    Circadian,
+}
+
+impl From<Color> for ColorSetting {
+    fn from(color: Color) -> Self {
+        ColorSetting::Custom(color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgb(red: u8, green: u8, blue: u8) -> Color {
+        Color { red, green, blue }
+    }
+
+    #[test]
+    fn parses_colors() {
+        let cases = [
+            ("#ff8800", rgb(255, 136, 0)),
+            ("FF8800", rgb(255, 136, 0)),
+            ("#f80", rgb(255, 136, 0)),
+            ("  #F80 ", rgb(255, 136, 0)),
+            ("orange", rgb(255, 165, 0)),
+            ("Rebecca Purple", rgb(102, 51, 153)),
+            ("rgb(255, 136, 0)", rgb(255, 136, 0)),
+            ("rgb(255 136 0)", rgb(255, 136, 0)),
+            ("rgb(100%, 53.4%, 0%)", rgb(255, 136, 0)),
+            ("rgb(100%, 136, 0)", rgb(255, 136, 0)),
+            ("hsl(0, 100%, 50%)", rgb(255, 0, 0)),
+            ("hsl(120deg, 100, 25)", rgb(0, 128, 0)),
+            ("hsl(-120, 100%, 50%)", rgb(0, 0, 255)),
+            ("hsv(60, 100%, 100%)", rgb(255, 255, 0)),
+            ("hsb(0, 0%, 50%)", rgb(128, 128, 128)),
+            ("6600K", rgb(255, 255, 255)),
+            ("1000k", rgb(255, 68, 0)),
+        ];
+        for (text, color) in cases {
+            assert_eq!(text.parse::<Color>().unwrap(), color, "{}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_colors() {
+        let cases = [
+            "", "#", "#ff88", "#ff880", "#ggg", "notacolor",
+            "rgb(256, 0, 0)", "rgb(-1, 0, 0)", "rgb(101%, 0, 0)", "rgb(1, 2)", "rgb(1, 2, 3, 4)",
+            "rgb(red, 0, 0)", "rgb(nan, 0, 0)", "cmyk(0, 0, 0)", "rgb(0, 0, 0",
+            "hsl(nan, 50%, 50%)", "hsl(inf, 50%, 50%)", "hsl(0, 150%, 50%)",
+            "999K", "40001K", "k",
+        ];
+        for text in cases {
+            assert!(text.parse::<Color>().is_err(), "{:?} parsed", text);
+        }
+    }
+
+    #[test]
+    fn kelvin_follows_the_black_body() {
+        assert_eq!(Color::from_kelvin(1000.0), rgb(255, 68, 0));
+        assert_eq!(Color::from_kelvin(2700.0), rgb(255, 167, 87));
+        assert_eq!(Color::from_kelvin(6600.0), Color::WHITE);
+        // Out of range temperatures clamp to the ends
+        assert_eq!(Color::from_kelvin(100.0), Color::from_kelvin(1000.0));
+        assert_eq!(Color::from_kelvin(100_000.0), Color::from_kelvin(40000.0));
+        // Warmer is redder
+        let (warm, cool) = (Color::from_kelvin(2000.0), Color::from_kelvin(10000.0));
+        assert!(warm.red >= cool.red && warm.blue < cool.blue);
+    }
+
+    #[test]
+    fn mixes_through_oklab() {
+        let colors = [Color::OFF, Color::WHITE, Color::RED, rgb(0, 255, 0), rgb(0, 0, 255), rgb(255, 136, 0), rgb(12, 34, 56)];
+        for from in colors {
+            // Every color survives the trip to Oklab and back
+            assert_eq!(from.mix(from, 0.5), from, "{:?}", from);
+            for to in colors {
+                assert_eq!(from.mix(to, 0.0), from, "{:?} to {:?}", from, to);
+                assert_eq!(from.mix(to, 1.0), to, "{:?} to {:?}", from, to);
+            }
+        }
+        // Greys stay grey, at Oklab's middle lightness
+        assert_eq!(Color::OFF.mix(Color::WHITE, 0.5), rgb(99, 99, 99));
+    }
+}
@@ -0,0 +1,768 @@
+use crate::effects::{EffectKind, EffectParams};
+use crate::rgb_controller::profiles::{MAX_KELVIN, MIN_KELVIN};
+use crate::solar::{Location, SolarEvent};
+use crate::{Color, ColorSetting, Command, RgbCommand, Transition, TransitionSpec};
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Datelike, Days as DayCount, Local, LocalResult, NaiveDate, NaiveTime, TimeZone, Weekday};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+// Cron rules that can't fire within this many days never will, e.g. 31 February
+const CRON_SEARCH_DAYS: u64 = 366 * 4;
+// Solar rules fire at most a day either side of their event
+const MAX_SOLAR_OFFSET: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The `[schedule]` table: rules that switch the lights by the clock.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Schedule {
+    /// How long a manual change holds the schedule off.
+    #[serde(rename = "override")]
+    pub override_period: Span,
+    #[serde(rename = "rule")]
+    pub rules: Vec<Rule>,
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self { override_period: Span(Duration::from_secs(60 * 60)), rules: Vec::new() }
+    }
+}
+
+impl Schedule {
+    /// Checks the rules; those following the sun need a `location`.
+    pub fn validate(&self, location: Option<&Location>) -> Result<()> {
+        for (index, rule) in self.rules.iter().enumerate() {
+            rule.validate(location).map_err(|e| anyhow!("rule {}: {}", index + 1, e))?;
+        }
+        Ok(())
+    }
+
+    /// The rule that fires first after `after`, and when.
+    pub fn next_after<Tz: TimeZone>(&self, after: DateTime<Tz>, location: Option<&Location>) -> Option<(usize, DateTime<Tz>)> {
+        self.rules.iter().enumerate()
+            .filter_map(|(index, rule)| Some((index, rule.next_after(after.clone(), location)?)))
+            .min_by(|(_, a), (_, b)| a.cmp(b))
+    }
+}
+
+/// One `[[schedule.rule]]`: when to fire, and what to show.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    /// A time of day, `"20:00"`, or a solar event, `"sunset - 30m"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub at: Option<TimeOfDay>,
+    /// Five cron fields instead of `at`, `"0 20 * * 1-5"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cron: Option<Cron>,
+    /// Days the rule fires on, on top of `at` or `cron`.
+    #[serde(default, skip_serializing_if = "Days::is_every_day")]
+    pub days: Days,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<Color>,
+    /// White at this color temperature, in Kelvin.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effect: Option<String>,
+    /// Global brightness, from 0 to 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<f32>,
+    /// Fade to the new setting over this long instead of the usual transition.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fade: Option<Span>,
+    /// Devices to switch; every device when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<String>,
+}
+
+impl Rule {
+    fn validate(&self, location: Option<&Location>) -> Result<()> {
+        if self.at.is_some() == self.cron.is_some() {
+            bail!("needs either `at` or `cron`");
+        }
+        if let Some(TimeOfDay::Solar { event, offset_secs }) = self.at {
+            if location.is_none() {
+                bail!("following {} needs a [location] with your latitude and longitude", event);
+            }
+            if offset_secs.unsigned_abs() > MAX_SOLAR_OFFSET.as_secs() {
+                bail!("offsets from {} go up to {} either way", event, Span(MAX_SOLAR_OFFSET));
+            }
+        }
+        let settings = [self.profile.is_some(), self.color.is_some(), self.temperature.is_some(), self.effect.is_some()];
+        match settings.iter().filter(|&&set| set).count() {
+            0 if self.brightness.is_none() => bail!("needs a profile, color, temperature, effect or brightness"),
+            0 | 1 => {}
+            _ => bail!("can only pick one of profile, color, temperature and effect"),
+        }
+        if let Some(kelvin) = self.temperature {
+            if !(MIN_KELVIN..=MAX_KELVIN).contains(&kelvin) {
+                bail!("color temperatures go from {}K to {}K, not {}K", MIN_KELVIN, MAX_KELVIN, kelvin);
+            }
+        }
+        if let Some(name) = &self.effect {
+            if EffectKind::from_name(name).is_none() {
+                bail!("no effect named {:?}", name);
+            }
+        }
+        if let Some(brightness) = self.brightness {
+            if !(0.0..=1.0).contains(&brightness) {
+                bail!("brightness goes from 0 to 1, not {}", brightness);
+            }
+        }
+        Ok(())
+    }
+
+    /// When the rule next fires, strictly after `after`. Rules following the
+    /// sun never fire without a `location`. Times of day are read on the
+    /// clock of `after`'s time zone.
+    pub fn next_after<Tz: TimeZone>(&self, after: DateTime<Tz>, location: Option<&Location>) -> Option<DateTime<Tz>> {
+        let zone = after.timezone();
+        // The day before too, as an offset can push an event past midnight
+        let start = after.date_naive().pred_opt()?;
+        let days = match self.at {
+            Some(TimeOfDay::Clock { .. }) => 9,
+            // Near the poles the sun can skip an event for months
+            Some(TimeOfDay::Solar { .. }) => 367,
+            None => CRON_SEARCH_DAYS,
+        };
+        (0..days)
+            .filter_map(|offset| start.checked_add_days(DayCount::new(offset)))
+            .filter(|date| self.days.contains(date.weekday()))
+            .find_map(|date| self.times_on(date, location, zone.clone()).find(|time| *time > after))
+    }
+
+    // Every time the rule fires on `date`, in order
+    fn times_on<'a, Tz: TimeZone + 'a>(&'a self, date: NaiveDate, location: Option<&Location>, zone: Tz) -> Box<dyn Iterator<Item = DateTime<Tz>> + 'a> {
+        let times: Box<dyn Iterator<Item = NaiveTime>> = match (&self.at, &self.cron) {
+            (Some(TimeOfDay::Clock { hour, minute }), _) => {
+                Box::new(NaiveTime::from_hms_opt(*hour, *minute, 0).into_iter())
+            }
+            (Some(TimeOfDay::Solar { event, offset_secs }), _) => {
+                let time = location
+                    .and_then(|location| event.time_on(date, location))
+                    .and_then(|time| time.with_timezone(&zone).checked_add_signed(chrono::Duration::try_seconds(*offset_secs)?));
+                return Box::new(time.into_iter());
+            }
+            (None, Some(cron)) => Box::new(cron.times_on(date)),
+            (None, None) => Box::new(std::iter::empty()),
+        };
+        // Times skipped by a daylight saving change don't fire that day, and
+        // times that happen twice fire the first time
+        Box::new(times.filter_map(move |time| match zone.from_local_datetime(&date.and_time(time)) {
+            LocalResult::Single(time) => Some(time),
+            LocalResult::Ambiguous(first, second) => Some(first.min(second)),
+            LocalResult::None => None,
+        }))
+    }
+
+    /// What to send the daemon when the rule fires.
+    pub fn commands(&self) -> Vec<Command> {
+        let mut commands = Vec::new();
+        if let Some(brightness) = self.brightness {
+            commands.push(Command::SetBrightness(brightness));
+        }
+        let command = if let Some(profile) = &self.profile {
+            Command::SetProfile(ColorSetting::Profile(profile.clone()))
+        } else if let Some(color) = self.color {
+            Command::SetColor(RgbCommand::from(color))
+        } else if let Some(kelvin) = self.temperature {
+            Command::SetProfile(ColorSetting::Temperature(kelvin))
+        } else if let Some(effect) = &self.effect {
+            Command::SetEffect { name: effect.clone(), params: EffectParams::default() }
+        } else {
+            return commands;
+        };
+        let transition = match self.fade {
+            Some(Span(duration)) => TransitionSpec {
+                style: Some(Transition::Fade),
+                duration_ms: Some(duration.as_millis() as u64),
+                easing: None,
+            },
+            None => TransitionSpec::default(),
+        };
+        commands.push(command.with_transition(transition).targeted(self.devices.clone()));
+        commands
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.at, &self.cron) {
+            (Some(at), _) => write!(f, "at {}", at)?,
+            (None, Some(cron)) => write!(f, "cron {:?}", cron.to_string())?,
+            (None, None) => write!(f, "never")?,
+        }
+        if !self.days.is_every_day() {
+            write!(f, " on {}", self.days)?;
+        }
+        let mut actions = Vec::new();
+        if let Some(profile) = &self.profile {
+            actions.push(format!("profile {}", profile));
+        }
+        if let Some(color) = self.color {
+            actions.push(format!("RGB({}, {}, {})", color.red, color.green, color.blue));
+        }
+        if let Some(kelvin) = self.temperature {
+            actions.push(format!("{}K", kelvin));
+        }
+        if let Some(effect) = &self.effect {
+            actions.push(format!("effect {}", effect));
+        }
+        if let Some(brightness) = self.brightness {
+            actions.push(format!("brightness {:.0}%", brightness * 100.0));
+        }
+        write!(f, ": {}", actions.join(", "))?;
+        if let Some(fade) = &self.fade {
+            write!(f, ", {} fade", fade)?;
+        }
+        if !self.devices.is_empty() {
+            write!(f, " on {}", self.devices.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// A length of time written like `10m`, `90s` or `1h30m`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Span(pub Duration);
+
+impl FromStr for Span {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("{:?} is not a length of time like 10m, 90s or 1h30m", s);
+        let s = s.trim();
+        if s == "0" {
+            return Ok(Span(Duration::ZERO));
+        }
+        let mut seconds = 0.0;
+        let mut rest = s;
+        while !rest.is_empty() {
+            let split = rest.find(|c: char| !(c.is_ascii_digit() || c == '.')).ok_or_else(invalid)?;
+            let (number, tail) = rest.split_at(split);
+            let number: f64 = number.parse().map_err(|_| invalid())?;
+            let unit = tail.find(|c: char| c.is_ascii_digit()).unwrap_or(tail.len());
+            let (unit, tail) = tail.split_at(unit);
+            seconds += number * match unit.trim() {
+                "h" => 3600.0,
+                "m" | "min" => 60.0,
+                "s" => 1.0,
+                "ms" => 0.001,
+                _ => return Err(invalid()),
+            };
+            rest = tail.trim_start();
+        }
+        if s.is_empty() {
+            return Err(invalid());
+        }
+        Duration::try_from_secs_f64(seconds).map(Span).map_err(|_| invalid())
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.0.as_secs();
+        let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+        let mut written = false;
+        for (value, unit) in [(hours, "h"), (minutes, "m"), (seconds, "s")] {
+            if value > 0 {
+                write!(f, "{}{}", value, unit)?;
+                written = true;
+            }
+        }
+        if !written {
+            write!(f, "{}", if self.0.is_zero() { "0".to_string() } else { format!("{}ms", self.0.as_millis()) })?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<String> for Span {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Span> for String {
+    fn from(span: Span) -> Self {
+        span.to_string()
+    }
+}
+
+/// When in the day a rule fires: a time on the 24-hour clock, `"07:30"`,
+/// or a solar event with an optional offset, `"sunset - 30m"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum TimeOfDay {
+    Clock { hour: u32, minute: u32 },
+    /// Negative offsets are before the event.
+    Solar { event: SolarEvent, offset_secs: i64 },
+}
+
+impl FromStr for TimeOfDay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.starts_with(|c: char| c.is_ascii_digit()) {
+            let invalid = || format!("{:?} is not a time like 20:00", s);
+            let (hour, minute) = s.split_once(':').ok_or_else(invalid)?;
+            let hour: u32 = hour.trim().parse().map_err(|_| invalid())?;
+            let minute: u32 = minute.trim().parse().map_err(|_| invalid())?;
+            if hour > 23 || minute > 59 {
+                return Err(invalid());
+            }
+            return Ok(TimeOfDay::Clock { hour, minute });
+        }
+        let (event, offset_secs) = match s.rfind(['+', '-']) {
+            // Event names have dashes of their own, so only a sign followed by a number counts
+            Some(index) if s[index + 1..].trim_start().starts_with(|c: char| c.is_ascii_digit()) => {
+                let offset: Span = s[index + 1..].parse()?;
+                if offset.0 > MAX_SOLAR_OFFSET {
+                    return Err(format!("{:?} is too far from the event; offsets go up to {}", s, Span(MAX_SOLAR_OFFSET)));
+                }
+                let seconds = offset.0.as_secs() as i64;
+                (&s[..index], if s[index..].starts_with('-') { -seconds } else { seconds })
+            }
+            _ => (s, 0),
+        };
+        Ok(TimeOfDay::Solar { event: event.parse()?, offset_secs })
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeOfDay::Clock { hour, minute } => write!(f, "{:02}:{:02}", hour, minute),
+            TimeOfDay::Solar { event, offset_secs: 0 } => write!(f, "{}", event),
+            TimeOfDay::Solar { event, offset_secs } => {
+                let sign = if *offset_secs < 0 { '-' } else { '+' };
+                write!(f, "{} {} {}", event, sign, Span(Duration::from_secs(offset_secs.unsigned_abs())))
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<TimeOfDay> for String {
+    fn from(time: TimeOfDay) -> Self {
+        time.to_string()
+    }
+}
+
+/// Days of the week, from names like `"mon"`, `"weekdays"` or `"weekends"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<String>", into = "Vec<String>")]
+pub struct Days(u8);
+
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+impl Days {
+    const EVERY_DAY: Days = Days(0b111_1111);
+    const WEEKDAYS: Days = Days(0b001_1111);
+    const WEEKENDS: Days = Days(0b110_0000);
+
+    pub fn contains(self, day: Weekday) -> bool {
+        self.0 & (1 << day.num_days_from_monday()) != 0
+    }
+
+    pub fn is_every_day(&self) -> bool {
+        *self == Days::EVERY_DAY
+    }
+
+    fn parse_day(name: &str) -> Option<u8> {
+        let name = name.trim().to_ascii_lowercase();
+        DAY_NAMES.iter().position(|day| name.starts_with(day)).map(|index| index as u8)
+    }
+}
+
+impl Default for Days {
+    fn default() -> Self {
+        Days::EVERY_DAY
+    }
+}
+
+impl TryFrom<Vec<String>> for Days {
+    type Error = String;
+
+    fn try_from(names: Vec<String>) -> Result<Self, Self::Error> {
+        if names.is_empty() {
+            return Ok(Days::EVERY_DAY);
+        }
+        let mut days = 0;
+        for name in &names {
+            days |= match name.trim().to_ascii_lowercase().as_str() {
+                "daily" => Days::EVERY_DAY.0,
+                "weekdays" => Days::WEEKDAYS.0,
+                "weekends" => Days::WEEKENDS.0,
+                _ => 1 << Days::parse_day(name).ok_or_else(|| format!("{:?} is not a day of the week", name))?,
+            };
+        }
+        Ok(Days(days))
+    }
+}
+
+impl From<Days> for Vec<String> {
+    fn from(days: Days) -> Self {
+        match days {
+            Days::EVERY_DAY => vec!["daily".to_string()],
+            Days::WEEKDAYS => vec!["weekdays".to_string()],
+            Days::WEEKENDS => vec!["weekends".to_string()],
+            Days(mask) => DAY_NAMES.iter().enumerate()
+                .filter(|&(index, _)| mask & (1 << index) != 0)
+                .map(|(_, name)| name.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for Days {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Vec::<String>::from(*self).join(", "))
+    }
+}
+
+/// Minute, hour, day of month, month and day of week, as in crontab(5).
+/// Each field takes `*`, numbers, ranges, lists and `/` steps; days of the
+/// week can also be names.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cron {
+    source: String,
+    minutes: u64,
+    hours: u32,
+    days_of_month: u32,
+    months: u16,
+    days_of_week: u8,
+    // Restricting both days fires on either, as cron does; a field
+    // starting with `*` narrows the other instead
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+impl Cron {
+    fn times_on(&self, date: NaiveDate) -> impl Iterator<Item = NaiveTime> + '_ {
+        let month_matches = self.months & (1 << date.month()) != 0;
+        let day_of_month = self.days_of_month & (1 << date.day()) != 0;
+        let day_of_week = self.days_of_week & (1 << date.weekday().num_days_from_sunday()) != 0;
+        let day_matches = if self.any_day_of_month || self.any_day_of_week {
+            day_of_month && day_of_week
+        } else {
+            day_of_month || day_of_week
+        };
+        let hours = (0..24).filter(move |hour| month_matches && day_matches && self.hours & (1 << hour) != 0);
+        hours.flat_map(move |hour| {
+            (0..60)
+                .filter(move |minute| self.minutes & (1 << minute) != 0)
+                .filter_map(move |minute| NaiveTime::from_hms_opt(hour, minute, 0))
+        })
+    }
+
+    // One field as a bit per allowed value
+    fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+        let value = |s: &str| -> Result<u32, String> {
+            let lower = s.to_ascii_lowercase();
+            if let Some(index) = names.iter().position(|name| lower.starts_with(name)) {
+                return Ok(index as u32);
+            }
+            s.parse().map_err(|_| format!("{:?} is not a number", s))
+        };
+        let mut bits = 0u64;
+        for part in field.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, step.parse::<u32>().map_err(|_| format!("bad step in {:?}", part))?),
+                None => (part, 1),
+            };
+            let (from, to) = match range {
+                "*" => (min, max),
+                _ => match range.split_once('-') {
+                    Some((from, to)) => (value(from)?, value(to)?),
+                    None => {
+                        let single = value(range)?;
+                        (single, if step > 1 { max } else { single })
+                    }
+                },
+            };
+            if step == 0 || from < min || to > max || from > to {
+                return Err(format!("{:?} is out of range {}-{}", part, min, max));
+            }
+            for value in (from..=to).step_by(step as usize) {
+                bits |= 1 << value;
+            }
+        }
+        Ok(bits)
+    }
+}
+
+impl FromStr for Cron {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minute, hour, day_of_month, month, day_of_week] = fields[..] else {
+            return Err(format!("{:?} needs five fields: minute, hour, day of month, month and day of week", s));
+        };
+        let sunday_first = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+        let mut days_of_week = Cron::parse_field(day_of_week, 0, 7, &sunday_first)?;
+        // 7 is Sunday too
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week |= 1;
+        }
+        Ok(Cron {
+            source: fields.join(" "),
+            minutes: Cron::parse_field(minute, 0, 59, &[])?,
+            hours: Cron::parse_field(hour, 0, 23, &[])? as u32,
+            days_of_month: Cron::parse_field(day_of_month, 1, 31, &[])? as u32,
+            months: Cron::parse_field(month, 1, 12, &[])? as u16,
+            days_of_week: (days_of_week & 0x7f) as u8,
+            // Like cron, `*/2` counts as `*` when deciding how the day fields combine
+            any_day_of_month: day_of_month.starts_with('*'),
+            any_day_of_week: day_of_week.starts_with('*'),
+        })
+    }
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl TryFrom<String> for Cron {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Cron> for String {
+    fn from(cron: Cron) -> Self {
+        cron.source
+    }
+}
+
+/// Snapshot returned for `Command::GetSchedule`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScheduleStatus {
+    pub rules: Vec<RuleStatus>,
+    /// Paused with `PauseSchedule`; until `paused_until` if set, otherwise
+    /// until resumed.
+    pub paused: bool,
+    pub paused_until: Option<DateTime<Local>>,
+    /// A manual change holds the schedule off until then.
+    pub override_until: Option<DateTime<Local>>,
+    /// Today's, when a location is configured and the sun rises and sets.
+    #[serde(default)]
+    pub sunrise: Option<DateTime<Local>>,
+    #[serde(default)]
+    pub sunset: Option<DateTime<Local>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RuleStatus {
+    pub rule: Rule,
+    pub next: Option<DateTime<Local>>,
+}
+
+impl ScheduleStatus {
+    /// The rule that fires next, ignoring pauses and overrides.
+    pub fn next(&self) -> Option<&RuleStatus> {
+        self.rules.iter().filter(|status| status.next.is_some()).min_by_key(|status| status.next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration as TimeDelta, FixedOffset, NaiveDateTime};
+
+    // Central European time, whose clocks go forward at 02:00 on the last
+    // Sunday of March and back at 03:00 on the last Sunday of October.
+    // Rules run on whatever zone they're given, so the tests don't depend
+    // on the machine's.
+    #[derive(Debug, Clone, Copy)]
+    struct Cet;
+
+    impl Cet {
+        const WINTER: i32 = 3600;
+        const SUMMER: i32 = 7200;
+
+        // Clocks change at 01:00 UTC on the last Sunday of the month
+        fn change(year: i32, month: u32) -> NaiveDateTime {
+            let last = NaiveDate::from_ymd_opt(year, month + 1, 1).unwrap().pred_opt().unwrap();
+            let sunday = last - TimeDelta::days(last.weekday().num_days_from_sunday().into());
+            sunday.and_hms_opt(1, 0, 0).unwrap()
+        }
+
+        fn offset_at(utc: &NaiveDateTime) -> FixedOffset {
+            let summer = (Self::change(utc.year(), 3)..Self::change(utc.year(), 10)).contains(utc);
+            FixedOffset::east_opt(if summer { Self::SUMMER } else { Self::WINTER }).unwrap()
+        }
+    }
+
+    impl TimeZone for Cet {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            Cet
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_time(NaiveTime::MIN))
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            let fits = |secs: i32| {
+                let offset = FixedOffset::east_opt(secs).unwrap();
+                (Self::offset_at(&(*local - offset)) == offset).then_some(offset)
+            };
+            match (fits(Self::WINTER), fits(Self::SUMMER)) {
+                (Some(winter), Some(summer)) => LocalResult::Ambiguous(winter, summer),
+                (Some(offset), None) | (None, Some(offset)) => LocalResult::Single(offset),
+                (None, None) => LocalResult::None,
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            Self::offset_at(&utc.and_time(NaiveTime::MIN))
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            Self::offset_at(utc)
+        }
+    }
+
+    fn local(time: &str) -> DateTime<Cet> {
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap()
+            .and_local_timezone(Cet).earliest().unwrap()
+    }
+
+    fn rule(toml: &str) -> Rule {
+        let rule: Rule = toml::from_str(&format!("{}\nprofile = \"Night\"", toml)).unwrap();
+        rule.validate(None).unwrap();
+        rule
+    }
+
+    fn next(rule: &Rule, after: &str) -> String {
+        rule.next_after(local(after), None).unwrap().format("%a %Y-%m-%d %H:%M").to_string()
+    }
+
+    #[test]
+    fn fires_at_a_time_of_day() {
+        let rule = rule("at = \"20:00\"");
+        assert_eq!(next(&rule, "2024-06-03 12:00"), "Mon 2024-06-03 20:00");
+        assert_eq!(next(&rule, "2024-06-03 20:00"), "Tue 2024-06-04 20:00");
+
+        let rule = self::rule("at = \"07:30\"\ndays = [\"weekends\"]");
+        assert_eq!(next(&rule, "2024-06-03 12:00"), "Sat 2024-06-08 07:30");
+    }
+
+    #[test]
+    fn skips_times_lost_to_daylight_saving() {
+        // 02:30 never happens on the day the clocks go forward
+        let rule = rule("at = \"02:30\"");
+        assert_eq!(next(&rule, "2024-03-30 12:00"), "Mon 2024-04-01 02:30");
+        // and happens twice when they go back, but fires once
+        let first = rule.next_after(local("2024-10-26 12:00"), None).unwrap();
+        assert_eq!(first.to_rfc3339(), "2024-10-27T02:30:00+02:00");
+        assert_eq!(next(&rule, "2024-10-27 02:30"), "Mon 2024-10-28 02:30");
+
+        let rule = self::rule("cron = \"30 2 * * *\"");
+        assert_eq!(next(&rule, "2024-03-30 12:00"), "Mon 2024-04-01 02:30");
+    }
+
+    #[test]
+    fn cron_steps() {
+        let cron: Cron = "*/15 * * * *".parse().unwrap();
+        let date = NaiveDate::from_ymd_opt(2024, 6, 3).unwrap();
+        assert_eq!(cron.times_on(date).count(), 96);
+        assert_eq!(cron.times_on(date).nth(1), NaiveTime::from_hms_opt(0, 15, 0));
+
+        let cron: Cron = "0 */6 * * *".parse().unwrap();
+        let hours: Vec<u32> = cron.times_on(date).map(|time| chrono::Timelike::hour(&time)).collect();
+        assert_eq!(hours, [0, 6, 12, 18]);
+
+        let cron: Cron = "5-20/5 9 * * *".parse().unwrap();
+        assert_eq!(cron.times_on(date).count(), 4);
+
+        let rule = rule("cron = \"0 9 */10 * *\"");
+        assert_eq!(next(&rule, "2024-06-03 12:00"), "Tue 2024-06-11 09:00");
+    }
+
+    #[test]
+    fn cron_days() {
+        // Fields starting with `*` narrow the other day field instead of
+        // firing on either, so this is Mondays on odd days only
+        let rule = rule("cron = \"0 12 */2 * mon\"");
+        assert_eq!(next(&rule, "2024-06-04 13:00"), "Mon 2024-06-17 12:00");
+        let rule = self::rule("cron = \"0 12 * * */2\"");
+        assert_eq!(next(&rule, "2024-06-04 13:00"), "Thu 2024-06-06 12:00");
+        // Restricting both fires on either
+        let rule = self::rule("cron = \"0 12 13 * fri\"");
+        assert_eq!(next(&rule, "2024-06-08 13:00"), "Thu 2024-06-13 12:00");
+        assert_eq!(next(&rule, "2024-06-13 13:00"), "Fri 2024-06-14 12:00");
+        // Both 0 and 7 are Sunday
+        let rule = self::rule("cron = \"0 12 * * 7\"");
+        assert_eq!(next(&rule, "2024-06-04 13:00"), "Sun 2024-06-09 12:00");
+        // Rules that can never fire don't
+        let never = self::rule("cron = \"0 12 31 2 *\"");
+        assert_eq!(never.next_after(local("2024-06-04 13:00"), None), None);
+    }
+
+    #[test]
+    fn rejects_bad_cron_lines() {
+        for line in ["* * * *", "60 * * * *", "* 24 * * *", "* * 0 * *", "* * * 13 *", "* * * * 8", "*/0 * * * *", "5-1 * * * *", "x * * * *"] {
+            assert!(line.parse::<Cron>().is_err(), "{:?} parsed", line);
+        }
+    }
+
+    #[test]
+    fn follows_the_sun() {
+        let berlin = Location { latitude: 52.52, longitude: 13.405 };
+        let rule: Rule = toml::from_str("at = \"sunset - 30m\"\nprofile = \"Night\"").unwrap();
+        assert!(rule.validate(None).is_err());
+        let time = rule.next_after(local("2024-06-21 12:00"), Some(&berlin)).unwrap();
+        assert_eq!(time.format("%H:%M").to_string(), "21:03");
+        assert_eq!(rule.next_after(local("2024-06-21 12:00"), None), None);
+
+        let far = Rule { at: Some(TimeOfDay::Solar { event: SolarEvent::Sunset, offset_secs: i64::MAX }), ..rule };
+        assert!(far.validate(Some(&berlin)).is_err());
+        assert_eq!(far.next_after(local("2024-06-21 12:00"), Some(&berlin)), None);
+    }
+
+    #[test]
+    fn parses_times_and_spans() {
+        assert_eq!("7:05".parse(), Ok(TimeOfDay::Clock { hour: 7, minute: 5 }));
+        assert_eq!("nautical-dawn+15m".parse(), Ok(TimeOfDay::Solar { event: SolarEvent::NauticalDawn, offset_secs: 900 }));
+        assert_eq!("sunset - 1h30m".parse(), Ok(TimeOfDay::Solar { event: SolarEvent::Sunset, offset_secs: -5400 }));
+        assert!("24:00".parse::<TimeOfDay>().is_err());
+        assert_eq!("sunrise - 24h".parse(), Ok(TimeOfDay::Solar { event: SolarEvent::Sunrise, offset_secs: -86400 }));
+        assert!("sunset + 10000000000h".parse::<TimeOfDay>().is_err());
+        assert!("sunset + 24h1s".parse::<TimeOfDay>().is_err());
+        assert!("moonrise".parse::<TimeOfDay>().is_err());
+
+        assert_eq!("1h30m".parse(), Ok(Span(Duration::from_secs(5400))));
+        assert_eq!("90s".parse(), Ok(Span(Duration::from_secs(90))));
+        assert_eq!("250ms".parse(), Ok(Span(Duration::from_millis(250))));
+        assert_eq!("0".parse(), Ok(Span(Duration::ZERO)));
+        for span in ["", "10", "10x", "h", "1e400h", "99999999999999999999h"] {
+            assert!(span.parse::<Span>().is_err(), "{:?} parsed", span);
+        }
+    }
+}
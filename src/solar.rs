@@ -0,0 +1,247 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+// Julian day of 2000-01-01 12:00 UTC, and of the Unix epoch
+const J2000: f64 = 2451545.0;
+const UNIX_EPOCH_JD: f64 = 2440587.5;
+// Tilt of the Earth's axis, in degrees
+const OBLIQUITY: f64 = 23.4397;
+
+/// Where the lights are, for working out when the sun rises and sets.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+    /// Degrees north of the equator; south is negative.
+    pub latitude: f64,
+    /// Degrees east of Greenwich; west is negative.
+    pub longitude: f64,
+}
+
+impl Location {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !(-90.0..=90.0).contains(&self.latitude) || !(-180.0..=180.0).contains(&self.longitude) {
+            anyhow::bail!("latitude goes from -90 to 90 and longitude from -180 to 180, not {}, {}",
+                self.latitude, self.longitude);
+        }
+        Ok(())
+    }
+}
+
+/// A point in the sun's day. Twilights are named by their morning and
+/// evening ends; plain dawn and dusk are civil twilight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SolarEvent {
+    AstronomicalDawn,
+    NauticalDawn,
+    Dawn,
+    Sunrise,
+    Noon,
+    Sunset,
+    Dusk,
+    NauticalDusk,
+    AstronomicalDusk,
+}
+
+impl SolarEvent {
+    const ALL: [SolarEvent; 9] = [
+        SolarEvent::AstronomicalDawn,
+        SolarEvent::NauticalDawn,
+        SolarEvent::Dawn,
+        SolarEvent::Sunrise,
+        SolarEvent::Noon,
+        SolarEvent::Sunset,
+        SolarEvent::Dusk,
+        SolarEvent::NauticalDusk,
+        SolarEvent::AstronomicalDusk,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SolarEvent::AstronomicalDawn => "astronomical-dawn",
+            SolarEvent::NauticalDawn => "nautical-dawn",
+            SolarEvent::Dawn => "dawn",
+            SolarEvent::Sunrise => "sunrise",
+            SolarEvent::Noon => "noon",
+            SolarEvent::Sunset => "sunset",
+            SolarEvent::Dusk => "dusk",
+            SolarEvent::NauticalDusk => "nautical-dusk",
+            SolarEvent::AstronomicalDusk => "astronomical-dusk",
+        }
+    }
+
+    // Where the sun's centre is at the event, in degrees above the horizon,
+    // and whether it is on the way up
+    fn elevation(self) -> Option<(f64, bool)> {
+        match self {
+            SolarEvent::AstronomicalDawn => Some((-18.0, true)),
+            SolarEvent::NauticalDawn => Some((-12.0, true)),
+            SolarEvent::Dawn => Some((-6.0, true)),
+            // Refraction and the size of the disc put the top edge on the horizon
+            SolarEvent::Sunrise => Some((-0.833, true)),
+            SolarEvent::Noon => None,
+            SolarEvent::Sunset => Some((-0.833, false)),
+            SolarEvent::Dusk => Some((-6.0, false)),
+            SolarEvent::NauticalDusk => Some((-12.0, false)),
+            SolarEvent::AstronomicalDusk => Some((-18.0, false)),
+        }
+    }
+
+    /// When the event happens at `location` on `date`, or `None` on days
+    /// the sun never gets that high or low, as near the poles.
+    pub fn time_on(self, date: NaiveDate, location: &Location) -> Option<DateTime<Utc>> {
+        let sun = SolarDay::new(date, location);
+        let julian = match self.elevation() {
+            None => sun.transit,
+            Some((elevation, rising)) => {
+                let hour_angle = sun.hour_angle(elevation, location.latitude)?;
+                if rising { sun.transit - hour_angle / 360.0 } else { sun.transit + hour_angle / 360.0 }
+            }
+        };
+        from_julian(julian)
+    }
+}
+
+impl FromStr for SolarEvent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_ascii_lowercase().replace(['_', ' '], "-");
+        let name = match name.as_str() {
+            "civil-dawn" => "dawn",
+            "civil-dusk" => "dusk",
+            "solar-noon" => "noon",
+            name => name,
+        };
+        SolarEvent::ALL.into_iter().find(|event| event.name() == name)
+            .ok_or_else(|| format!("{:?} is not a solar event like sunrise, sunset, dawn or dusk", s))
+    }
+}
+
+impl fmt::Display for SolarEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// How high the sun is above the horizon at `location` and `time`, in degrees.
+pub fn elevation(location: &Location, time: DateTime<Utc>) -> f64 {
+    let sun = SolarDay::new(time.date_naive(), location);
+    let julian = time.timestamp() as f64 / 86400.0 + UNIX_EPOCH_JD;
+    let hour_angle = ((julian - sun.transit) * 360.0).to_radians();
+    let latitude = location.latitude.to_radians();
+    let sin = latitude.sin() * sun.declination.sin() + latitude.cos() * sun.declination.cos() * hour_angle.cos();
+    sin.clamp(-1.0, 1.0).asin().to_degrees()
+}
+
+// The sun's path on one day, from the sunrise equation
+struct SolarDay {
+    // Julian day of solar noon
+    transit: f64,
+    // Declination, in radians
+    declination: f64,
+}
+
+impl SolarDay {
+    fn new(date: NaiveDate, location: &Location) -> Self {
+        // Days since J2000 at noon UTC, shifted to local solar noon
+        let noon = julian_day(date) - J2000 + 0.0008;
+        let mean_noon = noon.round() - location.longitude / 360.0;
+        let anomaly = (357.5291 + 0.98560028 * mean_noon).rem_euclid(360.0).to_radians();
+        let center = 1.9148 * anomaly.sin() + 0.02 * (2.0 * anomaly).sin() + 0.0003 * (3.0 * anomaly).sin();
+        let longitude = (anomaly.to_degrees() + center + 180.0 + 102.9372).rem_euclid(360.0).to_radians();
+        let transit = J2000 + mean_noon + 0.0053 * anomaly.sin() - 0.0069 * (2.0 * longitude).sin();
+        let declination = (longitude.sin() * OBLIQUITY.to_radians().sin()).asin();
+        Self { transit, declination }
+    }
+
+    // Degrees the sun turns between reaching `elevation` and solar noon
+    fn hour_angle(&self, elevation: f64, latitude: f64) -> Option<f64> {
+        let latitude = latitude.to_radians();
+        let cos = (elevation.to_radians().sin() - latitude.sin() * self.declination.sin())
+            / (latitude.cos() * self.declination.cos());
+        (-1.0..=1.0).contains(&cos).then(|| cos.acos().to_degrees())
+    }
+}
+
+// Julian day at noon UTC on `date`
+fn julian_day(date: NaiveDate) -> f64 {
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap_or_default();
+    (date - epoch).num_days() as f64 + UNIX_EPOCH_JD + 0.5
+}
+
+fn from_julian(julian: f64) -> Option<DateTime<Utc>> {
+    let seconds = (julian - UNIX_EPOCH_JD) * 86400.0;
+    DateTime::from_timestamp(seconds.floor() as i64, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BERLIN: Location = Location { latitude: 52.52, longitude: 13.405 };
+    const NEW_YORK: Location = Location { latitude: 40.7128, longitude: -74.006 };
+    const TROMSO: Location = Location { latitude: 69.6492, longitude: 18.9553 };
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    // Published times are to the minute
+    fn assert_near(event: SolarEvent, date: NaiveDate, location: &Location, expected: &str) {
+        let expected: DateTime<Utc> = expected.parse().unwrap();
+        let actual = event.time_on(date, location).unwrap();
+        assert!((actual - expected).num_seconds().abs() <= 60, "{} on {}: {} instead of {}", event, date, actual, expected);
+    }
+
+    #[test]
+    fn matches_published_times() {
+        // Berlin on the summer solstice: 04:43 and 21:33 CEST
+        assert_near(SolarEvent::Sunrise, date(2024, 6, 21), &BERLIN, "2024-06-21T02:43:00Z");
+        assert_near(SolarEvent::Sunset, date(2024, 6, 21), &BERLIN, "2024-06-21T19:33:00Z");
+        // New York on the winter solstice: 07:16 and 16:32 EST
+        assert_near(SolarEvent::Sunrise, date(2024, 12, 21), &NEW_YORK, "2024-12-21T12:16:00Z");
+        assert_near(SolarEvent::Sunset, date(2024, 12, 21), &NEW_YORK, "2024-12-21T21:32:00Z");
+        assert_near(SolarEvent::Noon, date(2024, 12, 21), &NEW_YORK, "2024-12-21T16:54:00Z");
+    }
+
+    #[test]
+    fn twilights_surround_the_day() {
+        let times: Vec<_> = SolarEvent::ALL.iter()
+            .map(|event| event.time_on(date(2024, 12, 21), &NEW_YORK).unwrap())
+            .collect();
+        assert!(times.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", times);
+    }
+
+    #[test]
+    fn polar_days_and_nights_have_no_sunrise() {
+        // Midnight sun: the sun never sets, nor gets dark enough for any twilight
+        let midsummer = date(2024, 6, 21);
+        for event in SolarEvent::ALL {
+            assert_eq!(event.time_on(midsummer, &TROMSO).is_some(), event == SolarEvent::Noon, "{}", event);
+        }
+        // Polar night: twilight, but no sunrise or sunset
+        let midwinter = date(2024, 12, 21);
+        assert_eq!(SolarEvent::Sunrise.time_on(midwinter, &TROMSO), None);
+        assert_eq!(SolarEvent::Sunset.time_on(midwinter, &TROMSO), None);
+        assert!(SolarEvent::Dawn.time_on(midwinter, &TROMSO).is_some());
+        assert!(elevation(&TROMSO, SolarEvent::Noon.time_on(midwinter, &TROMSO).unwrap()) < 0.0);
+    }
+
+    #[test]
+    fn elevation_peaks_at_noon() {
+        let noon = SolarEvent::Noon.time_on(date(2024, 6, 21), &BERLIN).unwrap();
+        // 90° less the latitude, plus the tilt of the Earth
+        assert!((elevation(&BERLIN, noon) - (90.0 - 52.52 + OBLIQUITY)).abs() < 0.1);
+        let sunset = SolarEvent::Sunset.time_on(date(2024, 6, 21), &BERLIN).unwrap();
+        assert!((elevation(&BERLIN, sunset) + 0.833).abs() < 0.1);
+    }
+
+    #[test]
+    fn parses_event_names() {
+        assert_eq!("Sunset".parse(), Ok(SolarEvent::Sunset));
+        assert_eq!("civil_dawn".parse(), Ok(SolarEvent::Dawn));
+        assert_eq!("nautical dusk".parse(), Ok(SolarEvent::NauticalDusk));
+        assert!("moonrise".parse::<SolarEvent>().is_err());
+    }
+}